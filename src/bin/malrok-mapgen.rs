@@ -0,0 +1,67 @@
+//! Generates a map from a settings file, without a window or a GPU, and writes its heightmap,
+//! biome map and preview images to disk.
+//!
+//! Usage: `malrok-mapgen <settings.ron> [output directory]`
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use malrok::map::{self, HeightmapImageSettings, MapSettings};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(settings_path) = args.next() else {
+        eprintln!("Usage: malrok-mapgen <settings.ron> [output directory]");
+        return ExitCode::FAILURE;
+    };
+    let output = args.next().map(PathBuf::from).unwrap_or_default();
+
+    match generate(Path::new(&settings_path), &output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Failed to generate map from {}: {}", settings_path, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn generate(settings_path: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let settings: MapSettings = ron::de::from_str(&fs::read_to_string(settings_path)?)?;
+
+    let layers = map::generate_layers(&settings);
+    let generated = map::generate_map(&settings, &layers).ok_or("no heightmap layer is active")?;
+    let heightmap = &generated.heightmap;
+
+    if !output.as_os_str().is_empty() {
+        fs::create_dir_all(output)?;
+    }
+
+    map::export_grayscale_png(heightmap, output.join("heightmap.png"))?;
+
+    map::save_png(
+        heightmap.width,
+        heightmap.depth,
+        map::render_biome_map(&generated.biome_map),
+        output.join("biomes.png"),
+    )?;
+
+    map::export_png(
+        heightmap,
+        &HeightmapImageSettings::default(),
+        output.join("preview.png"),
+    )?;
+
+    println!(
+        "Generated {}x{} map with {} rivers at {}",
+        heightmap.width,
+        heightmap.depth,
+        generated.hydrology.rivers.len(),
+        output.display()
+    );
+
+    Ok(())
+}
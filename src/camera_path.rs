@@ -0,0 +1,528 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    fly_by_cam::{CameraUpdate, FlyByCamera},
+    input_context::{accepts_shortcuts, ActiveController, InputBindings},
+    shutdown::ShutdownRequested,
+};
+
+/// Key which starts and stops recording the camera path.
+const RECORD_KEY: KeyCode = KeyCode::F8;
+
+/// Key which starts and stops playing the recorded camera path.
+const PLAY_KEY: KeyCode = KeyCode::F9;
+
+/// Held with [`PLAY_KEY`] to play the path in benchmark mode.
+const BENCHMARK_MODIFIER: KeyCode = KeyCode::ShiftLeft;
+
+/// File the camera path is saved to and played from.
+pub const CAMERA_PATH_FILE: &str = "camera_path.ron";
+
+/// Seconds between recorded keyframes. The path is smoothed between them on playback.
+const SAMPLE_INTERVAL: f32 = 0.25;
+
+/// Records the active [`FlyByCamera`] transform to [`CAMERA_PATH_FILE`] and plays it back as a
+/// smooth path, used for trailers and benchmarks:
+///
+/// - `F8`: starts and stops recording;
+/// - `F9`: starts and stops playback;
+/// - `Shift + F9`: plays in benchmark mode, logging frame time statistics at the end.
+pub struct CameraPathPlugin;
+
+impl Plugin for CameraPathPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraPathState>()
+            .add_event::<ShutdownRequested>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                Update,
+                (
+                    (toggle_recording, toggle_playback).run_if(accepts_shortcuts),
+                    record_path,
+                    play_path,
+                    save_on_shutdown.run_if(on_event::<ShutdownRequested>()),
+                )
+                    .chain()
+                    .after(CameraUpdate),
+            );
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CameraPathError {
+    #[error("Failed to access camera path file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse camera path file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("Failed to serialize camera path: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+/// Camera transform at a point in time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// Seconds since the recording started.
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+/// Keyframes of a recorded camera path, ordered by time.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CameraPathError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CameraPathError> {
+        let content = ron::ser::to_string_pretty(self, default())?;
+        Ok(fs::write(path, content)?)
+    }
+
+    /// Length of the path, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.keyframes
+            .last()
+            .map(|keyframe| keyframe.time)
+            .unwrap_or_default()
+    }
+
+    /// Camera transform at `time`, interpolated with a Catmull-Rom spline through the keyframe
+    /// translations and spherical interpolation of the rotations.
+    pub fn sample(&self, time: f32) -> Option<Transform> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+
+        // Segment which starts on `i`, with its neighbors used as control points
+        let i = keyframes
+            .partition_point(|keyframe| keyframe.time <= time)
+            .saturating_sub(1)
+            .min(last.saturating_sub(1));
+        let [p0, p1, p2, p3] = [i.saturating_sub(1), i, (i + 1).min(last), (i + 2).min(last)]
+            .map(|index| keyframes[index]);
+
+        let length = p2.time - p1.time;
+        let t = if length > 0.0 {
+            ((time - p1.time) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        Some(Transform {
+            translation: catmull_rom(
+                p0.translation,
+                p1.translation,
+                p2.translation,
+                p3.translation,
+                t,
+            ),
+            rotation: p1.rotation.slerp(p2.rotation, t),
+            ..default()
+        })
+    }
+}
+
+/// Point at `t`, in range [0, 1], of the uniform Catmull-Rom segment between `p1` and `p2`.
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Eases in and out, so playback starts and stops smoothly.
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[derive(Resource, Default)]
+enum CameraPathState {
+    #[default]
+    Idle,
+    Recording {
+        path: CameraPath,
+        elapsed: f32,
+    },
+    Playing {
+        path: CameraPath,
+        elapsed: f32,
+        /// Real frame times, in seconds, when playing in benchmark mode.
+        frame_times: Option<Vec<f32>>,
+    },
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Record camera path", [RECORD_KEY]);
+    // Benchmark is the same action with a modifier, so it's a single binding which doesn't conflict
+    bindings.set(
+        None,
+        "Play camera path (benchmark with Shift)",
+        [
+            UserInput::from(PLAY_KEY),
+            UserInput::chord([BENCHMARK_MODIFIER, PLAY_KEY]),
+        ],
+    );
+}
+
+fn toggle_recording(
+    input: Res<Input<KeyCode>>,
+    mut state: ResMut<CameraPathState>,
+    q_camera: Query<&Transform, (With<FlyByCamera>, With<ActiveController>)>,
+) {
+    if !input.just_pressed(RECORD_KEY) {
+        return;
+    }
+
+    match &mut *state {
+        CameraPathState::Recording { path, elapsed } => {
+            if let Ok(transform) = q_camera.get_single() {
+                path.keyframes.push(keyframe(*elapsed, transform));
+            }
+
+            match path.save(CAMERA_PATH_FILE) {
+                Ok(()) => info!(
+                    "Camera path of {:.1}s saved to {}",
+                    path.duration(),
+                    CAMERA_PATH_FILE
+                ),
+                Err(err) => error!("Failed to save camera path: {}", err),
+            }
+            *state = CameraPathState::Idle;
+        }
+        CameraPathState::Playing { .. } => {
+            warn!("Stop playing the camera path before recording it");
+        }
+        CameraPathState::Idle => {
+            let Ok(transform) = q_camera.get_single() else {
+                warn!("There is no camera to record");
+                return;
+            };
+
+            info!("Recording camera path");
+            *state = CameraPathState::Recording {
+                path: CameraPath {
+                    keyframes: vec![keyframe(0.0, transform)],
+                },
+                elapsed: 0.0,
+            };
+        }
+    }
+}
+
+/// Saves the path being recorded, so it isn't lost when the game is closed.
+fn save_on_shutdown(mut state: ResMut<CameraPathState>) {
+    if let CameraPathState::Recording { path, .. } = &*state {
+        match path.save(CAMERA_PATH_FILE) {
+            Ok(()) => info!("Camera path being recorded saved to {}", CAMERA_PATH_FILE),
+            Err(err) => error!("Failed to save camera path: {}", err),
+        }
+        *state = CameraPathState::Idle;
+    }
+}
+
+fn toggle_playback(input: Res<Input<KeyCode>>, mut state: ResMut<CameraPathState>) {
+    if !input.just_pressed(PLAY_KEY) {
+        return;
+    }
+
+    match *state {
+        CameraPathState::Idle => (),
+        CameraPathState::Recording { .. } => {
+            warn!("Stop recording the camera path before playing it");
+            return;
+        }
+        CameraPathState::Playing { .. } => {
+            info!("Camera path playback stopped");
+            *state = CameraPathState::Idle;
+            return;
+        }
+    }
+
+    let path = match CameraPath::load(CAMERA_PATH_FILE) {
+        Ok(path) if path.keyframes.len() >= 2 => path,
+        Ok(_) => {
+            warn!("Camera path needs at least two keyframes to be played");
+            return;
+        }
+        Err(err) => {
+            error!("Failed to load camera path: {}", err);
+            return;
+        }
+    };
+
+    let benchmark = input.pressed(BENCHMARK_MODIFIER);
+    info!(
+        "Playing camera path of {:.1}s{}",
+        path.duration(),
+        if benchmark { " in benchmark mode" } else { "" }
+    );
+    *state = CameraPathState::Playing {
+        path,
+        elapsed: 0.0,
+        frame_times: benchmark.then(Vec::new),
+    };
+}
+
+fn keyframe(time: f32, transform: &Transform) -> Keyframe {
+    Keyframe {
+        time,
+        translation: transform.translation,
+        rotation: transform.rotation,
+    }
+}
+
+fn record_path(
+    time: Res<Time>,
+    mut state: ResMut<CameraPathState>,
+    q_camera: Query<&Transform, (With<FlyByCamera>, With<ActiveController>)>,
+) {
+    let CameraPathState::Recording { path, elapsed } = &mut *state else {
+        return;
+    };
+    let Ok(transform) = q_camera.get_single() else {
+        return;
+    };
+
+    *elapsed += time.delta_seconds();
+
+    let last_time = path.duration();
+    if *elapsed - last_time >= SAMPLE_INTERVAL {
+        path.keyframes.push(keyframe(*elapsed, transform));
+    }
+}
+
+/// Moves the camera along the path, after [`CameraUpdate`] so it isn't moved by input. Benchmarks
+/// measure real time, since virtual time is scaled and clamped.
+fn play_path(
+    time: Res<Time>,
+    real_time: Res<Time<Real>>,
+    mut state: ResMut<CameraPathState>,
+    mut q_camera: Query<(&mut Transform, &mut FlyByCamera), With<ActiveController>>,
+) {
+    let CameraPathState::Playing {
+        path,
+        elapsed,
+        frame_times,
+    } = &mut *state
+    else {
+        return;
+    };
+    let Ok((mut transform, mut camera)) = q_camera.get_single_mut() else {
+        return;
+    };
+
+    // First frame includes loading the path, so it isn't measured
+    if *elapsed > 0.0 {
+        if let Some(frame_times) = frame_times {
+            frame_times.push(real_time.delta_seconds());
+        }
+    }
+
+    let duration = path.duration();
+    let progress = (*elapsed / duration).min(1.0);
+    if let Some(sampled) = path.sample(smoothstep(progress) * duration) {
+        transform.translation = sampled.translation;
+        transform.rotation = sampled.rotation;
+    }
+    camera.velocity = Vec3::ZERO;
+    camera.orbit_focus = None;
+
+    *elapsed += time.delta_seconds();
+
+    if progress >= 1.0 {
+        if let Some(frame_times) = frame_times {
+            report_frame_times(frame_times);
+        }
+        info!("Camera path playback finished");
+        *state = CameraPathState::Idle;
+    }
+}
+
+/// Frame time statistics of a benchmark, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameTimeStats {
+    count: usize,
+    average: f32,
+    best: f32,
+    worst: f32,
+    percentile_99: f32,
+}
+
+/// Computes the statistics of the given frame times, sorting them, or `None` when there is none.
+fn frame_time_stats(frame_times: &mut [f32]) -> Option<FrameTimeStats> {
+    if frame_times.is_empty() {
+        return None;
+    }
+
+    frame_times.sort_by(f32::total_cmp);
+
+    // Nearest rank, the smallest frame time which is at least as slow as 99% of the frames
+    let count = frame_times.len();
+    let percentile_99_rank = (count * 99).div_ceil(100);
+
+    Some(FrameTimeStats {
+        count,
+        average: frame_times.iter().sum::<f32>() / count as f32,
+        best: frame_times[0],
+        worst: frame_times[count - 1],
+        percentile_99: frame_times[percentile_99_rank - 1],
+    })
+}
+
+/// Logs average, best, worst and 99th percentile frame times of a benchmark.
+fn report_frame_times(frame_times: &mut [f32]) {
+    let Some(stats) = frame_time_stats(frame_times) else {
+        return;
+    };
+
+    info!(
+        "Benchmark: {} frames, {:.1} FPS, frame time {:.2} ms average, {:.2} ms best, \
+        {:.2} ms worst, {:.2} ms 99th percentile",
+        stats.count,
+        1.0 / stats.average,
+        stats.average * 1000.0,
+        stats.best * 1000.0,
+        stats.worst * 1000.0,
+        stats.percentile_99 * 1000.0,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(keyframes: &[(f32, Vec3)]) -> CameraPath {
+        CameraPath {
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, translation)| Keyframe {
+                    time,
+                    translation,
+                    rotation: Quat::IDENTITY,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_inner_points() {
+        let [p0, p1, p2, p3] = [
+            Vec3::new(-1.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(3.0, 1.0, 2.0),
+            Vec3::new(4.0, -2.0, 5.0),
+        ];
+
+        assert!(catmull_rom(p0, p1, p2, p3, 0.0).abs_diff_eq(p1, 1e-6));
+        assert!(catmull_rom(p0, p1, p2, p3, 1.0).abs_diff_eq(p2, 1e-6));
+    }
+
+    #[test]
+    fn catmull_rom_is_linear_on_evenly_spaced_line() {
+        let point = |i: f32| Vec3::new(i, 2.0 * i, -i);
+
+        let sampled = catmull_rom(point(0.0), point(1.0), point(2.0), point(3.0), 0.25);
+
+        assert!(sampled.abs_diff_eq(point(1.25), 1e-6));
+    }
+
+    #[test]
+    fn empty_path_has_no_sample() {
+        assert_eq!(CameraPath::default().sample(0.0), None);
+    }
+
+    #[test]
+    fn single_keyframe_is_sampled_at_any_time() {
+        let path = path(&[(0.0, Vec3::ONE)]);
+
+        for time in [-1.0, 0.0, 5.0] {
+            let sampled = path.sample(time).unwrap();
+            assert_eq!(sampled.translation, Vec3::ONE);
+        }
+    }
+
+    #[test]
+    fn equal_timestamps_dont_divide_by_zero() {
+        let path = path(&[(1.0, Vec3::X), (1.0, Vec3::Y)]);
+
+        for time in [0.0, 1.0, 2.0] {
+            let sampled = path.sample(time).unwrap();
+            assert!(sampled.translation.is_finite());
+            assert!(sampled.rotation.is_finite());
+        }
+    }
+
+    #[test]
+    fn sample_passes_through_keyframes_and_clamps() {
+        let path = path(&[
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::new(1.0, 0.0, 0.0)),
+            (2.0, Vec3::new(2.0, 1.0, 0.0)),
+            (3.0, Vec3::new(2.0, 1.0, 3.0)),
+        ]);
+
+        for keyframe in &path.keyframes {
+            let sampled = path.sample(keyframe.time).unwrap();
+            assert!(sampled.translation.abs_diff_eq(keyframe.translation, 1e-6));
+        }
+
+        let before = path.sample(-1.0).unwrap();
+        assert!(before.translation.abs_diff_eq(Vec3::ZERO, 1e-6));
+        let after = path.sample(10.0).unwrap();
+        assert!(after
+            .translation
+            .abs_diff_eq(Vec3::new(2.0, 1.0, 3.0), 1e-6));
+    }
+
+    #[test]
+    fn no_stats_without_frames() {
+        assert_eq!(frame_time_stats(&mut []), None);
+    }
+
+    #[test]
+    fn stats_of_single_frame() {
+        let stats = frame_time_stats(&mut [0.02]).unwrap();
+
+        assert_eq!(stats.count, 1);
+        assert_eq!(stats.best, 0.02);
+        assert_eq!(stats.worst, 0.02);
+        assert_eq!(stats.percentile_99, 0.02);
+    }
+
+    #[test]
+    fn percentile_99_of_hundred_frames_skips_slowest() {
+        // Frames of 1 to 100 ms, shuffled
+        let mut frame_times = (1..=100)
+            .map(|i| ((i * 37) % 100 + 1) as f32 / 1000.0)
+            .collect::<Vec<_>>();
+
+        let stats = frame_time_stats(&mut frame_times).unwrap();
+
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.best, 0.001);
+        assert_eq!(stats.worst, 0.1);
+        assert_eq!(stats.percentile_99, 0.099);
+        assert!((stats.average - 0.0505).abs() < 1e-6);
+    }
+
+    #[test]
+    fn percentile_99_of_two_hundred_frames() {
+        let mut frame_times = (1..=200).map(|i| i as f32).collect::<Vec<_>>();
+
+        let stats = frame_time_stats(&mut frame_times).unwrap();
+
+        assert_eq!(stats.percentile_99, 198.0);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    render::settings::Backends,
+    window::{PresentMode, WindowMode, WindowResolution},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{fly_by_cam::KeyBindings, map::MapSettings, player::PlayerBindings};
+
+/// Config file loaded when none is given by `--config` or `MALROK_CONFIG`.
+const DEFAULT_CONFIG_PATH: &str = "malrok.ron";
+
+/// User controls file, saved by the controls menu and loaded over the config file.
+pub const CONTROLS_PATH: &str = "controls.ron";
+
+/// Prefix of environment variables which override config values.
+const ENV_PREFIX: &str = "MALROK_";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {path}: {source}")]
+    Ron {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+    #[error("Unknown config key {0}")]
+    UnknownKey(String),
+    #[error("Invalid value {value} for {key}: {reason}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    #[error("Missing value for {0}")]
+    MissingValue(String),
+    #[error("Failed to write {path}: {source}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to serialize {path}: {source}")]
+    Serialize { path: PathBuf, source: ron::Error },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// Whatever the platform supports best.
+    Auto,
+    #[default]
+    Vulkan,
+    Dx12,
+    Metal,
+    Gl,
+}
+
+impl RenderBackend {
+    pub fn backends(self) -> Backends {
+        match self {
+            RenderBackend::Auto => Backends::PRIMARY,
+            RenderBackend::Vulkan => Backends::VULKAN,
+            RenderBackend::Dx12 => Backends::DX12,
+            RenderBackend::Metal => Backends::METAL,
+            RenderBackend::Gl => Backends::GL,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub mode: WindowMode,
+    pub width: f32,
+    pub height: f32,
+    pub vsync: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            mode: WindowMode::Windowed,
+            width: 1280.0,
+            height: 720.0,
+            vsync: true,
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn window(&self) -> Window {
+        Window {
+            title: "Malrok".to_string(),
+            mode: self.mode,
+            resolution: WindowResolution::new(self.width, self.height),
+            present_mode: if self.vsync {
+                PresentMode::AutoVsync
+            } else {
+                PresentMode::AutoNoVsync
+            },
+            ..default()
+        }
+    }
+}
+
+/// Debug options. Debug plugins, like the world inspector, are only available when built with the
+/// `dev-tools` feature.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DebugConfig {
+    pub world_inspector: bool,
+    /// Spawns a grid of obstacles over the map, used to test movement and camera.
+    pub obstacle_grid: bool,
+    /// Spawns a second camera, drawn over a corner of the window, which can be flown around on
+    /// the free camera context.
+    pub picture_in_picture: bool,
+    /// Number of local players spawned on gameplay, which are switched with `Tab`.
+    pub test_players: u8,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            world_inspector: true,
+            obstacle_grid: false,
+            picture_in_picture: false,
+            test_players: 1,
+        }
+    }
+}
+
+/// Bindings of every action, changed on the controls menu.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Controls {
+    pub camera: KeyBindings,
+    pub player: PlayerBindings,
+}
+
+impl Controls {
+    /// Saves the controls as a RON file at `path`, which can be loaded later as a config layer.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let content = ron::ser::to_string_pretty(self, default()).map_err(|source| {
+            ConfigError::Serialize {
+                path: path.clone(),
+                source,
+            }
+        })?;
+        fs::write(&path, content).map_err(|source| ConfigError::Write { path, source })
+    }
+}
+
+/// Client settings, layered from the lowest to the highest priority: defaults, config file,
+/// controls file ([`CONTROLS_PATH`]), environment variables and command line arguments.
+///
+/// Environment variables and arguments use the path of the value, like `window.width`. Variables
+/// are upper case, prefixed by `MALROK_` and use `__` as separator, like `MALROK_WINDOW__WIDTH`,
+/// while arguments are given as `--window.width 1920` or `--window.width=1920`. Values use the
+/// same syntax of the config file.
+#[derive(Resource, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Runs only the game logic, without window or renderer.
+    pub headless: bool,
+    pub window: WindowConfig,
+    pub backend: RenderBackend,
+    pub debug: DebugConfig,
+    /// Map settings file the game starts with, in the same format used by `malrok-mapgen`.
+    pub map: Option<PathBuf>,
+    /// Position of the camera relative to the player it follows.
+    pub camera_position: Vec3,
+    /// Vertical field of view of the camera, in degrees.
+    pub fov: f32,
+    pub controls: Controls,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            window: default(),
+            backend: default(),
+            debug: default(),
+            map: None,
+            camera_position: Vec3::new(0.0, 6.0, 12.0),
+            fov: 45.0,
+            controls: default(),
+        }
+    }
+}
+
+fn read_ron<T: DeserializeOwned>(path: PathBuf) -> Result<T, ConfigError> {
+    let content = fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    ron::from_str(&content).map_err(|source| ConfigError::Ron { path, source })
+}
+
+fn parse<T: DeserializeOwned>(key: &str, value: &str) -> Result<T, ConfigError> {
+    ron::from_str(value).map_err(|err| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: err.to_string(),
+    })
+}
+
+impl ClientConfig {
+    /// Loads the config from every layer, using the arguments the game was started with.
+    /// Environment variables which can't be used are skipped, adding a message to `warnings`.
+    pub fn load(warnings: &mut Vec<String>) -> Result<Self, ConfigError> {
+        let args = env::args().skip(1).collect::<Vec<_>>();
+        Self::load_from(&args, env::vars_os(), Path::new(CONTROLS_PATH), warnings)
+    }
+
+    /// Loads the config from every layer, using the given arguments, environment variables and
+    /// controls file.
+    fn load_from(
+        args: &[String],
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+        controls_path: &Path,
+        warnings: &mut Vec<String>,
+    ) -> Result<Self, ConfigError> {
+        let overrides = parse_args(args)?;
+        let vars = vars.into_iter().collect::<Vec<_>>();
+
+        let path = overrides
+            .iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| {
+                vars.iter()
+                    .find(|(name, _)| *name == "MALROK_CONFIG")
+                    .map(|(_, value)| PathBuf::from(value))
+            });
+
+        let mut config = match path {
+            Some(path) => read_ron(path)?,
+            None if PathBuf::from(DEFAULT_CONFIG_PATH).exists() => {
+                read_ron(DEFAULT_CONFIG_PATH.into())?
+            }
+            None => Self::default(),
+        };
+
+        if controls_path.exists() {
+            config.controls = read_ron(controls_path.into())?;
+        }
+
+        for (name, value) in vars {
+            let Some(key) = name.to_str().and_then(|name| name.strip_prefix(ENV_PREFIX)) else {
+                continue;
+            };
+            if key == "CONFIG" {
+                continue;
+            }
+
+            let Some(value) = value.to_str() else {
+                warnings.push(format!(
+                    "Skipping {}{}, its value isn't UTF-8",
+                    ENV_PREFIX, key
+                ));
+                continue;
+            };
+
+            // Other programs may use the same prefix, so unknown keys aren't an error
+            match config.set(&key.to_lowercase().replace("__", "."), value) {
+                Err(ConfigError::UnknownKey(_)) => warnings.push(format!(
+                    "Skipping {}{}, it isn't a config key",
+                    ENV_PREFIX, key
+                )),
+                result => result?,
+            }
+        }
+
+        for (key, value) in overrides {
+            if key != "config" {
+                config.set(&key, &value)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Overrides the value at the given `key` path, parsing it from the config file syntax.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "headless" => self.headless = parse(key, value)?,
+            "window.mode" => self.window.mode = parse(key, value)?,
+            "window.width" => self.window.width = parse(key, value)?,
+            "window.height" => self.window.height = parse(key, value)?,
+            "window.vsync" => self.window.vsync = parse(key, value)?,
+            "backend" => self.backend = parse(key, value)?,
+            "debug.world_inspector" => self.debug.world_inspector = parse(key, value)?,
+            "debug.obstacle_grid" => self.debug.obstacle_grid = parse(key, value)?,
+            "debug.picture_in_picture" => self.debug.picture_in_picture = parse(key, value)?,
+            "debug.test_players" => self.debug.test_players = parse(key, value)?,
+            // Paths are common enough to not require quotes
+            "map" => self.map = Some(PathBuf::from(value)),
+            "camera_position" => self.camera_position = parse(key, value)?,
+            "fov" => self.fov = parse(key, value)?,
+            "controls.camera.movement" => self.controls.camera.movement = parse(key, value)?,
+            "controls.camera.look" => self.controls.camera.look = parse(key, value)?,
+            "controls.camera.turn" => self.controls.camera.turn = parse(key, value)?,
+            "controls.camera.up" => self.controls.camera.up = parse(key, value)?,
+            "controls.camera.down" => self.controls.camera.down = parse(key, value)?,
+            "controls.camera.boost" => self.controls.camera.boost = parse(key, value)?,
+            "controls.camera.orbit" => self.controls.camera.orbit = parse(key, value)?,
+            "controls.player.movement" => self.controls.player.movement = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Loads the map settings file the game starts with, if any.
+    pub fn map_settings(&self) -> Result<Option<MapSettings>, ConfigError> {
+        self.map.clone().map(read_ron).transpose()
+    }
+}
+
+/// Splits arguments in `key`, `value` pairs. Flags without value, like `--headless`, are `true`,
+/// unless followed by one, like `--headless false`.
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut pairs = vec![];
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.strip_prefix("--") else {
+            return Err(ConfigError::UnknownKey(arg.clone()));
+        };
+
+        if let Some((key, value)) = arg.split_once('=') {
+            pairs.push((key.to_string(), value.to_string()));
+        } else if arg == "headless" {
+            let value = args
+                .next_if(|value| !value.starts_with("--"))
+                .cloned()
+                .unwrap_or_else(|| "true".to_string());
+            pairs.push((arg.to_string(), value));
+        } else {
+            let value = args
+                .next_if(|value| !value.starts_with("--"))
+                .ok_or_else(|| ConfigError::MissingValue(arg.to_string()))?;
+            pairs.push((arg.to_string(), value.clone()));
+        }
+    }
+
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use leafwing_input_manager::{prelude::UserInput, user_input::InputKind};
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|&(name, value)| (name.into(), value.into()))
+            .collect()
+    }
+
+    /// Writes `content` to a file in the temporary directory, unique to the running tests.
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("malrok-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_both_value_syntaxes() {
+        let pairs = parse_args(&args(&["--window.width=1920", "--window.height", "1080"])).unwrap();
+
+        assert_eq!(
+            pairs,
+            [
+                ("window.width".to_string(), "1920".to_string()),
+                ("window.height".to_string(), "1080".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_headless_flag_with_optional_value() {
+        let pairs = parse_args(&args(&["--headless", "--fov", "60"])).unwrap();
+        assert_eq!(pairs[0], ("headless".to_string(), "true".to_string()));
+        assert_eq!(pairs[1], ("fov".to_string(), "60".to_string()));
+
+        let pairs = parse_args(&args(&["--headless", "false"])).unwrap();
+        assert_eq!(pairs, [("headless".to_string(), "false".to_string())]);
+    }
+
+    #[test]
+    fn rejects_invalid_args() {
+        assert!(matches!(
+            parse_args(&args(&["--fov"])),
+            Err(ConfigError::MissingValue(key)) if key == "fov"
+        ));
+        assert!(matches!(
+            parse_args(&args(&["fov"])),
+            Err(ConfigError::UnknownKey(key)) if key == "fov"
+        ));
+    }
+
+    #[test]
+    fn skips_unknown_env_keys() {
+        let mut warnings = vec![];
+        let config = ClientConfig::load_from(
+            &[],
+            vars(&[
+                ("MALROK_UNKNOWN", "1"),
+                ("MALROK_FOV", "50.0"),
+                ("OTHER_FOV", "10.0"),
+            ]),
+            Path::new("missing_controls.ron"),
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert_eq!(config.fov, 50.0);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("MALROK_UNKNOWN"));
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let config_path = temp_file(
+            "config.ron",
+            "(fov: 30.0, window: (width: 800.0, height: 700.0, vsync: false))",
+        );
+        let controls_path = temp_file(
+            "controls.ron",
+            "(player: (movement: [Single(Keyboard(Up))]))",
+        );
+
+        let mut warnings = vec![];
+        let config = ClientConfig::load_from(
+            &args(&["--config", config_path.to_str().unwrap(), "--fov=50.0"]),
+            vars(&[("MALROK_FOV", "40.0"), ("MALROK_WINDOW__HEIGHT", "600.0")]),
+            &controls_path,
+            &mut warnings,
+        )
+        .unwrap();
+
+        fs::remove_file(config_path).unwrap();
+        fs::remove_file(controls_path).unwrap();
+
+        // Defaults
+        assert_eq!(config.backend, RenderBackend::default());
+        // Config file
+        assert_eq!(config.window.width, 800.0);
+        assert!(!config.window.vsync);
+        // Controls file
+        assert_eq!(
+            config.controls.player.movement,
+            [UserInput::Single(InputKind::Keyboard(KeyCode::Up))]
+        );
+        // Environment variables
+        assert_eq!(config.window.height, 600.0);
+        // Arguments
+        assert_eq!(config.fov, 50.0);
+        assert!(warnings.is_empty());
+    }
+}
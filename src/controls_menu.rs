@@ -0,0 +1,410 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_inspector_egui::{
+    bevy_egui::EguiContexts,
+    egui::{self, Color32},
+};
+use leafwing_input_manager::{prelude::*, user_input::InputKind};
+
+use crate::{
+    config::{ClientConfig, Controls, CONTROLS_PATH},
+    fly_by_cam::{self, FlyByCameraConfig, KeyBindings},
+    input_context::{
+        accepts_shortcuts, InputBindings, InputContext, InputContextEvent, InputContextStack,
+        InputContextUpdate,
+    },
+    player::{PlayerBindings, PlayerControllerConfig},
+    shutdown::ShutdownRequested,
+};
+
+/// Key which opens the controls menu. It's closed by `Esc`, like any other [`InputContext::UI`].
+const TOGGLE_KEY: KeyCode = KeyCode::F10;
+
+/// How far, in range [0, 1], a stick must be moved to be bound.
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// Adds a controls menu, on the [`InputContext::UI`] context, which rebinds every action of
+/// [`Controls`], shows binding conflicts and saves them to [`CONTROLS_PATH`].
+///
+/// Changes are applied right away to [`FlyByCameraConfig`] and [`PlayerControllerConfig`], and
+/// unsaved ones are saved on [`ShutdownRequested`].
+pub struct ControlsMenuPlugin;
+
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ControlsMenu>()
+            .add_event::<ShutdownRequested>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                Update,
+                (
+                    open_menu.run_if(accepts_shortcuts),
+                    capture_input,
+                    controls_window,
+                    apply_controls.run_if(resource_changed::<ClientConfig>()),
+                    save_on_shutdown.run_if(on_event::<ShutdownRequested>()),
+                )
+                    .chain()
+                    .after(InputContextUpdate),
+            );
+    }
+}
+
+/// Which inputs can be bound to an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActionKind {
+    /// Pressed or not, like keys and gamepad buttons.
+    Button,
+    /// Two axes, either from sticks or from four buttons.
+    DualAxis,
+}
+
+/// An action which can be rebound on the menu.
+struct ControlAction<'a> {
+    /// Context and name the action is registered with on [`InputBindings`].
+    context: InputContext,
+    name: &'static str,
+    kind: ActionKind,
+    inputs: &'a mut Vec<UserInput>,
+    /// Adapts a captured stick to the action.
+    stick: fn(DualAxis) -> DualAxis,
+}
+
+fn control_actions(controls: &mut Controls) -> [ControlAction<'_>; 8] {
+    let Controls {
+        camera:
+            KeyBindings {
+                movement,
+                look,
+                turn,
+                up,
+                down,
+                boost,
+                orbit,
+            },
+        player: PlayerBindings {
+            movement: player_movement,
+        },
+    } = controls;
+
+    let action = |context, name, kind, inputs| ControlAction {
+        context,
+        name,
+        kind,
+        inputs,
+        stick: |stick| stick,
+    };
+
+    [
+        action(
+            InputContext::Gameplay,
+            "Move",
+            ActionKind::DualAxis,
+            player_movement,
+        ),
+        action(
+            InputContext::FreeCamera,
+            "Move",
+            ActionKind::DualAxis,
+            movement,
+        ),
+        action(InputContext::FreeCamera, "Look", ActionKind::DualAxis, look),
+        ControlAction {
+            stick: fly_by_cam::turn_stick,
+            ..action(InputContext::FreeCamera, "Turn", ActionKind::DualAxis, turn)
+        },
+        action(InputContext::FreeCamera, "Up", ActionKind::Button, up),
+        action(InputContext::FreeCamera, "Down", ActionKind::Button, down),
+        action(InputContext::FreeCamera, "Boost", ActionKind::Button, boost),
+        action(InputContext::FreeCamera, "Orbit", ActionKind::Button, orbit),
+    ]
+}
+
+/// Input being captured for an action.
+#[derive(Debug, Clone)]
+struct Capture {
+    /// Index of the action on [`control_actions`].
+    action: usize,
+    /// Buttons pressed so far, when capturing up, down, left and right of a virtual dpad.
+    directions: Vec<InputKind>,
+}
+
+#[derive(Resource, Default)]
+struct ControlsMenu {
+    open: bool,
+    /// Whether [`InputContext::UI`] was already pushed, so the menu is closed once it's popped.
+    context_pushed: bool,
+    capture: Option<Capture>,
+    /// Whether controls were changed on the menu since they were last saved.
+    unsaved: bool,
+    /// Result of the last save.
+    status: Option<String>,
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Controls menu", [TOGGLE_KEY]);
+}
+
+fn open_menu(
+    input: Res<Input<KeyCode>>,
+    mut menu: ResMut<ControlsMenu>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    if input.just_released(TOGGLE_KEY) && !menu.open {
+        menu.open = true;
+        menu.context_pushed = false;
+        events.send(InputContextEvent::Push(InputContext::UI));
+    }
+}
+
+/// Raw inputs read while capturing a binding.
+#[derive(SystemParam)]
+struct RawInputs<'w> {
+    keys: Res<'w, Input<KeyCode>>,
+    mouse_buttons: Res<'w, Input<MouseButton>>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+    gamepads: Res<'w, Gamepads>,
+}
+
+impl RawInputs<'_> {
+    /// First button pressed this frame. `Esc` is left to close the menu.
+    fn just_pressed(&self, ignore_mouse: bool) -> Option<InputKind> {
+        self.keys
+            .get_just_pressed()
+            .find(|&&key| key != KeyCode::Escape)
+            .map(|&key| InputKind::Keyboard(key))
+            .or_else(|| {
+                self.mouse_buttons
+                    .get_just_pressed()
+                    .find(|_| !ignore_mouse)
+                    .map(|&button| InputKind::Mouse(button))
+            })
+            .or_else(|| {
+                self.gamepad_buttons
+                    .get_just_pressed()
+                    .next()
+                    .map(|button| InputKind::GamepadButton(button.button_type))
+            })
+    }
+
+    /// Stick moved past [`STICK_THRESHOLD`] on any gamepad.
+    fn moved_stick(&self) -> Option<DualAxis> {
+        let moved = |x, y| {
+            self.gamepads.iter().any(|gamepad| {
+                let value = |axis_type| {
+                    self.gamepad_axes
+                        .get(GamepadAxis::new(gamepad, axis_type))
+                        .unwrap_or_default()
+                };
+                Vec2::new(value(x), value(y)).length() > STICK_THRESHOLD
+            })
+        };
+
+        if moved(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) {
+            Some(DualAxis::left_stick())
+        } else if moved(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY) {
+            Some(DualAxis::right_stick())
+        } else {
+            None
+        }
+    }
+}
+
+/// Captures the next input pressed, binding it to the action being captured.
+fn capture_input(
+    mut egui_contexts: EguiContexts,
+    mut menu: ResMut<ControlsMenu>,
+    mut config: ResMut<ClientConfig>,
+    inputs: RawInputs,
+) {
+    let Some(capture) = &mut menu.capture else {
+        return;
+    };
+
+    // Clicks on the menu itself aren't bindings
+    let button = inputs.just_pressed(egui_contexts.ctx_mut().is_pointer_over_area());
+    let stick = inputs.moved_stick();
+
+    let mut controls = config.controls.clone();
+    let action = &mut control_actions(&mut controls)[capture.action];
+
+    let captured = match (action.kind, button, stick) {
+        (ActionKind::Button, Some(button), _) => Some(UserInput::Single(button)),
+        (ActionKind::DualAxis, _, Some(stick)) if capture.directions.is_empty() => {
+            Some((action.stick)(stick).into())
+        }
+        (ActionKind::DualAxis, Some(button), _) => {
+            capture.directions.push(button);
+            match capture.directions[..] {
+                [up, down, left, right] => Some(
+                    VirtualDPad {
+                        up,
+                        down,
+                        left,
+                        right,
+                    }
+                    .into(),
+                ),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+
+    if let Some(input) = captured {
+        if !action.inputs.contains(&input) {
+            action.inputs.push(input);
+        }
+        config.controls = controls;
+        menu.capture = None;
+        menu.unsaved = true;
+    }
+}
+
+fn controls_window(
+    mut egui_contexts: EguiContexts,
+    mut menu: ResMut<ControlsMenu>,
+    mut config: ResMut<ClientConfig>,
+    bindings: Res<InputBindings>,
+    stack: Res<InputContextStack>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    if !menu.open {
+        return;
+    }
+
+    // Closed by `Esc`, or anything else which pops the UI context
+    if stack.contains(InputContext::UI) {
+        menu.context_pushed = true;
+    } else if menu.context_pushed {
+        menu.open = false;
+        menu.capture = None;
+        return;
+    }
+
+    let mut open = true;
+    let mut controls = config.controls.clone();
+
+    egui::Window::new("Controls")
+        .open(&mut open)
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            egui::Grid::new("controls").striped(true).show(ui, |ui| {
+                for (index, action) in control_actions(&mut controls).into_iter().enumerate() {
+                    ui.label(format!("{:?}", action.context));
+                    ui.label(action.name);
+
+                    ui.horizontal_wrapped(|ui| {
+                        let mut removed = None;
+                        for (i, input) in action.inputs.iter().enumerate() {
+                            if ui
+                                .button(input.to_string())
+                                .on_hover_text("Click to remove")
+                                .clicked()
+                            {
+                                removed = Some(i);
+                            }
+                        }
+                        if let Some(i) = removed {
+                            action.inputs.remove(i);
+                        }
+                    });
+
+                    match &menu.capture {
+                        Some(capture) if capture.action == index => {
+                            let prompt = match (action.kind, capture.directions.len()) {
+                                (ActionKind::Button, _) => "Press a button...",
+                                (ActionKind::DualAxis, 0) => "Move a stick or press up...",
+                                (ActionKind::DualAxis, 1) => "Press down...",
+                                (ActionKind::DualAxis, 2) => "Press left...",
+                                (ActionKind::DualAxis, _) => "Press right...",
+                            };
+                            if ui.button(prompt).on_hover_text("Click to cancel").clicked() {
+                                menu.capture = None;
+                            }
+                        }
+                        _ => {
+                            if ui.button("Add").clicked() {
+                                menu.capture = Some(Capture {
+                                    action: index,
+                                    directions: vec![],
+                                });
+                            }
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+
+            ui.separator();
+            for conflict in bindings.conflicts() {
+                ui.colored_label(Color32::RED, conflict.to_string());
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Reset to defaults").clicked() {
+                    controls = default();
+                    menu.capture = None;
+                }
+                if ui.button("Save").clicked() {
+                    menu.status = Some(match controls.save(CONTROLS_PATH) {
+                        Ok(()) => {
+                            menu.unsaved = false;
+                            format!("Saved to {}", CONTROLS_PATH)
+                        }
+                        Err(err) => err.to_string(),
+                    });
+                }
+            });
+
+            if let Some(status) = &menu.status {
+                ui.label(status);
+            }
+        });
+
+    if controls != config.controls {
+        config.controls = controls;
+        menu.unsaved = true;
+    }
+
+    if !open {
+        menu.open = false;
+        menu.capture = None;
+        events.send(InputContextEvent::Pop(InputContext::UI));
+    }
+}
+
+/// Applies changed controls to the camera and player, which then update their input maps and
+/// registered bindings.
+fn apply_controls(
+    config: Res<ClientConfig>,
+    camera_config: Option<ResMut<FlyByCameraConfig>>,
+    player_config: Option<ResMut<PlayerControllerConfig>>,
+) {
+    if let Some(mut camera_config) = camera_config {
+        if camera_config.bindings != config.controls.camera {
+            camera_config.bindings = config.controls.camera.clone();
+        }
+    }
+
+    if let Some(mut player_config) = player_config {
+        if player_config.bindings != config.controls.player {
+            player_config.bindings = config.controls.player.clone();
+        }
+    }
+}
+
+/// Saves controls changed on the menu, so they aren't lost when the game is closed.
+fn save_on_shutdown(mut menu: ResMut<ControlsMenu>, config: Res<ClientConfig>) {
+    if !menu.unsaved {
+        return;
+    }
+
+    match config.controls.save(CONTROLS_PATH) {
+        Ok(()) => {
+            menu.unsaved = false;
+            info!("Unsaved controls saved to {}", CONTROLS_PATH);
+        }
+        Err(err) => error!("Failed to save controls: {}", err),
+    }
+}
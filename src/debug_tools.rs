@@ -0,0 +1,111 @@
+use bevy::{
+    app::PluginGroupBuilder,
+    diagnostic::{DiagnosticsStore, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
+    pbr::wireframe::{WireframeConfig, WireframePlugin},
+    prelude::*,
+    render::{renderer::RenderDevice, settings::WgpuFeatures},
+};
+use bevy_inspector_egui::{bevy_egui::EguiContexts, egui, quick::WorldInspectorPlugin};
+
+use crate::{
+    camera_path::CameraPathPlugin,
+    input_context::{accepts_shortcuts, InputBindings},
+    map::{MapEditorPlugin, TerrainDebugPlugin},
+};
+
+/// Key which toggles wireframe rendering of every mesh.
+const WIREFRAME_KEY: KeyCode = KeyCode::F3;
+
+/// Tools used during development: inspectors, camera path recording, map editor, terrain debug
+/// views, wireframe toggling and diagnostics. Only available with the `dev-tools` feature.
+///
+/// Requires [`FlyByCameraPlugin`](crate::fly_by_cam::FlyByCameraPlugin).
+pub struct DebugToolsPlugin;
+
+impl PluginGroup for DebugToolsPlugin {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(WorldInspectorPlugin::new())
+            .add(CameraPathPlugin)
+            .add(MapEditorPlugin)
+            .add(TerrainDebugPlugin)
+            .add(WireframeTogglePlugin)
+            .add(DiagnosticsPlugin)
+    }
+}
+
+/// Toggles wireframe rendering of every mesh when [`WIREFRAME_KEY`] is pressed.
+pub struct WireframeTogglePlugin;
+
+impl Plugin for WireframeTogglePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(WireframePlugin)
+            .add_systems(Startup, register_bindings)
+            .add_systems(Update, toggle_wireframe.run_if(accepts_shortcuts));
+    }
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Toggle wireframe", [WIREFRAME_KEY]);
+}
+
+fn toggle_wireframe(
+    input: Res<Input<KeyCode>>,
+    render_device: Res<RenderDevice>,
+    mut config: ResMut<WireframeConfig>,
+) {
+    if !input.just_pressed(WIREFRAME_KEY) {
+        return;
+    }
+
+    if !supports_wireframes(&render_device) {
+        warn!("Wireframes aren't supported by this GPU");
+        return;
+    }
+
+    config.global = !config.global;
+}
+
+/// Wireframes need [`WgpuFeatures::POLYGON_MODE_LINE`], which isn't available on every GPU.
+pub fn supports_wireframes(render_device: &RenderDevice) -> bool {
+    render_device
+        .features()
+        .contains(WgpuFeatures::POLYGON_MODE_LINE)
+}
+
+/// Shows frame rate, frame time and entity count on a window.
+pub struct DiagnosticsPlugin;
+
+impl Plugin for DiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin))
+            .add_systems(Update, diagnostics_window);
+    }
+}
+
+fn diagnostics_window(mut egui_contexts: EguiContexts, diagnostics: Res<DiagnosticsStore>) {
+    let value = |id| {
+        diagnostics
+            .get(id)
+            .and_then(|diagnostic| diagnostic.smoothed())
+            .unwrap_or_default()
+    };
+
+    egui::Window::new("Diagnostics")
+        .resizable(false)
+        .default_open(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.label(format!(
+                "FPS: {:.0}",
+                value(FrameTimeDiagnosticsPlugin::FPS)
+            ));
+            ui.label(format!(
+                "Frame time: {:.2} ms",
+                value(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+            ));
+            ui.label(format!(
+                "Entities: {:.0}",
+                value(EntityCountDiagnosticsPlugin::ENTITY_COUNT)
+            ));
+        });
+}
@@ -1,23 +1,77 @@
-use bevy::prelude::*;
+use bevy::{
+    input::{
+        common_conditions::input_just_pressed,
+        mouse::{MouseScrollUnit, MouseWheel},
+    },
+    prelude::*,
+};
 
-use bevy::input::mouse::MouseMotion;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input_context::{
+    accepts_shortcuts, cycle_active_controller, ensure_active_controller, toggle_context,
+    ActiveController, InputBindings, InputContext, InputContextEvent, InputContextStack,
+};
+
+/// Key which toggles the [`InputContext::FreeCamera`] context.
+const TOGGLE_KEY: KeyCode = KeyCode::F1;
+
+/// Key which makes the next [`FlyByCamera`] the [`ActiveController`].
+const NEXT_CAMERA_KEY: KeyCode = KeyCode::Tab;
+
+/// How much each scroll wheel line multiplies or divides the move speed.
+const SPEED_STEP: f32 = 1.25;
+
+/// Range of the multiplier applied to [`FlyByCameraConfig::move_speed`] by the scroll wheel.
+const SPEED_SCALE_RANGE: (f32, f32) = (0.1, 10.0);
+
+/// Scroll wheels which report pixels, like touchpads, are converted to lines by this factor.
+const PIXELS_PER_LINE: f32 = 100.0;
 
 /// Adds [`FlyByCameraConfig`] resource and internals systems gated by [`is_active`] run criteria
-/// grouped on [`CameraUpdate`] system set.
+/// grouped on [`CameraUpdate`] system set. Inputs are read from [`CameraAction`]s, bound by
+/// [`FlyByCameraConfig::bindings`], and the camera is toggled by [`TOGGLE_KEY`].
+///
+/// Only the [`FlyByCamera`] tagged with [`ActiveController`] is moved, which is switched to the
+/// next one by [`NEXT_CAMERA_KEY`].
+///
+/// Requires [`InputContextPlugin`](crate::input_context::InputContextPlugin).
 pub struct FlyByCameraPlugin;
 
 impl Plugin for FlyByCameraPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<FlyByCameraConfig>()
+            .register_type::<FlyByCamera>()
+            .add_plugins(InputManagerPlugin::<CameraAction>::default())
+            .add_systems(Startup, register_bindings)
             .add_systems(
                 Update,
-                (move_camera, rotate_camera)
-                    .in_set(CameraUpdate)
-                    .run_if(is_active),
+                (
+                    toggle_camera.run_if(accepts_shortcuts),
+                    update_input_map,
+                    register_bindings.run_if(resource_changed::<FlyByCameraConfig>()),
+                    (
+                        cycle_active_controller::<FlyByCamera>
+                            .run_if(is_active.and_then(input_just_pressed(NEXT_CAMERA_KEY))),
+                        ensure_active_controller::<FlyByCamera>,
+                    )
+                        .chain(),
+                )
+                    .before(CameraUpdate),
             )
             .add_systems(
                 Update,
-                toggle_mouse_grab.run_if(resource_changed::<FlyByCameraConfig>()),
+                (
+                    toggle_orbit,
+                    adjust_speed,
+                    move_camera,
+                    rotate_camera,
+                    orbit_camera,
+                )
+                    .chain()
+                    .in_set(CameraUpdate)
+                    .run_if(is_active),
             );
     }
 }
@@ -26,67 +80,156 @@ impl Plugin for FlyByCameraPlugin {
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub struct CameraUpdate;
 
-/// Component used to tag entity camera.
-/// There can be only one Entity with this component.
-#[derive(Component, Reflect, Default)]
+/// Component used to tag entity camera, which also keeps its movement state.
+/// There can be many entities with this component, but only the [`ActiveController`] is moved.
+#[derive(Component, Reflect, Debug)]
 #[reflect(Component)]
-pub struct FlyByCamera;
+pub struct FlyByCamera {
+    /// Current velocity, in units per second, which accelerates towards the input direction and
+    /// is damped when there is no input.
+    pub velocity: Vec3,
+
+    /// Multiplier of [`FlyByCameraConfig::move_speed`], changed by the scroll wheel.
+    pub speed_scale: f32,
 
-/// Key bindings used internal systems to move and rotate camera.
-#[derive(Debug)]
+    /// Point the camera orbits around, when in orbit mode. Moving the camera moves this point.
+    pub orbit_focus: Option<Vec3>,
+}
+
+impl Default for FlyByCamera {
+    fn default() -> Self {
+        Self {
+            velocity: Vec3::ZERO,
+            speed_scale: 1.0,
+            orbit_focus: None,
+        }
+    }
+}
+
+/// Actions the camera can perform, bound to inputs by [`KeyBindings`].
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect, Deserialize, Serialize)]
+pub enum CameraAction {
+    /// Moves forwards, backwards and sideways.
+    Move,
+    /// Rotates the camera by the given amount, like mouse motion.
+    Look,
+    /// Rotates the camera at a rate, like gamepad sticks.
+    Turn,
+    /// Moves upwards.
+    Up,
+    /// Moves downwards.
+    Down,
+    /// Boosts move speed.
+    Boost,
+    /// Toggles orbiting around a point in front of the camera.
+    Orbit,
+}
+
+/// Inputs bound to each [`CameraAction`]. Each list replaces the default bindings of its action.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct KeyBindings {
-    /// Forwards move key binding, defaults to [`KeyCode::W`].
-    pub forward: KeyCode,
+    /// Move bindings, defaults to `WASD` and the left stick.
+    pub movement: Vec<UserInput>,
 
-    /// Backwards move key binding, defaults to [`KeyCode::S`].
-    pub backward: KeyCode,
+    /// Look bindings, defaults to the mouse motion.
+    pub look: Vec<UserInput>,
 
-    /// Leftwards move key binding, defaults to [`KeyCode::A`].
-    pub left: KeyCode,
+    /// Turn bindings, defaults to the right stick.
+    pub turn: Vec<UserInput>,
 
-    /// Rightwards move key binding, defaults to [`KeyCode::D`].
-    pub right: KeyCode,
+    /// Upwards move bindings, defaults to [`KeyCode::Space`] and the right trigger.
+    pub up: Vec<UserInput>,
 
-    /// Upwards move key binding, defaults to [`KeyCode::Space`].
-    pub up: KeyCode,
+    /// Downwards move bindings, defaults to [`KeyCode::ControlLeft`] and the left trigger.
+    pub down: Vec<UserInput>,
 
-    /// Downwards move key binding, defaults to [`KeyCode::LControl`].
-    pub down: KeyCode,
+    /// Move speed boost bindings, defaults to [`KeyCode::ShiftLeft`] and the left stick button.
+    pub boost: Vec<UserInput>,
 
-    /// Move speed boost key binding, defaults to [`KeyCode::LShift`].
-    pub boost: KeyCode,
+    /// Orbit mode toggle bindings, defaults to [`KeyCode::O`] and the right stick button.
+    pub orbit: Vec<UserInput>,
 }
 
 impl Default for KeyBindings {
     fn default() -> Self {
         Self {
-            forward: KeyCode::W,
-            backward: KeyCode::S,
-            left: KeyCode::A,
-            right: KeyCode::D,
-            up: KeyCode::Space,
-            down: KeyCode::ControlLeft,
-            boost: KeyCode::ShiftLeft,
+            movement: vec![VirtualDPad::wasd().into(), DualAxis::left_stick().into()],
+            look: vec![DualAxis::mouse_motion().into()],
+            turn: vec![turn_stick(DualAxis::right_stick()).into()],
+            up: vec![
+                KeyCode::Space.into(),
+                GamepadButtonType::RightTrigger2.into(),
+            ],
+            down: vec![
+                KeyCode::ControlLeft.into(),
+                GamepadButtonType::LeftTrigger2.into(),
+            ],
+            boost: vec![
+                KeyCode::ShiftLeft.into(),
+                GamepadButtonType::LeftThumb.into(),
+            ],
+            orbit: vec![KeyCode::O.into(), GamepadButtonType::RightThumb.into()],
         }
     }
 }
 
+impl KeyBindings {
+    /// Inputs bound to the given `action`.
+    pub fn get(&self, action: CameraAction) -> &[UserInput] {
+        match action {
+            CameraAction::Move => &self.movement,
+            CameraAction::Look => &self.look,
+            CameraAction::Turn => &self.turn,
+            CameraAction::Up => &self.up,
+            CameraAction::Down => &self.down,
+            CameraAction::Boost => &self.boost,
+            CameraAction::Orbit => &self.orbit,
+        }
+    }
+
+    /// Builds the [`InputMap`] used by the camera entity.
+    pub fn input_map(&self) -> InputMap<CameraAction> {
+        let mut input_map = InputMap::default();
+        for action in CameraAction::variants() {
+            input_map.insert_multiple(self.get(action).iter().map(|input| (input.clone(), action)));
+        }
+        input_map
+    }
+}
+
+/// Adapts a gamepad `stick` to be used by [`CameraAction::Turn`].
+pub fn turn_stick(mut stick: DualAxis) -> DualAxis {
+    // Mouse moves down on positive Y, while sticks move up
+    stick.y = stick.y.inverted();
+    stick
+}
+
 /// Allows to configure [`FlyByCamera`] behavior.
 #[derive(Debug, Resource)]
 pub struct FlyByCameraConfig {
-    /// Enable or disable internal systems. This flag is used by [`is_active`] run criteria.
-    pub active: bool,
-
-    /// Move speed in units.
+    /// Move speed in units per second.
     pub move_speed: f32,
 
-    /// Move speed when [`KeyBindings::boost`] is enabled
+    /// Move speed multiplier when [`CameraAction::Boost`] is pressed
     pub move_speed_boost: f32,
 
-    /// Rotate speed in units.
-    pub rotate_speed: f32,
+    /// How fast, per second, the velocity reaches the input direction.
+    pub acceleration: f32,
+
+    /// How fast, per second, the velocity decays when there is no input.
+    pub damping: f32,
+
+    /// Radians rotated per pixel of [`CameraAction::Look`].
+    pub look_sensitivity: f32,
+
+    /// Radians rotated per second by [`CameraAction::Turn`].
+    pub turn_speed: f32,
 
-    /// Key bindings used by camera. See [`KeyBindings`] for more info.
+    /// Distance, in units, of the point orbited by [`CameraAction::Orbit`].
+    pub orbit_distance: f32,
+
+    /// Inputs bound to camera actions. See [`KeyBindings`] for more info.
     pub bindings: KeyBindings,
 }
 
@@ -95,44 +238,162 @@ impl Default for FlyByCameraConfig {
         Self {
             move_speed: 10.0,
             move_speed_boost: 10.0,
-            rotate_speed: 0.25,
-            active: true,
-
+            acceleration: 8.0,
+            damping: 5.0,
+            look_sensitivity: 0.004,
+            turn_speed: 2.5,
+            orbit_distance: 20.0,
             bindings: KeyBindings::default(),
         }
     }
 }
 
-/// Returns [`ShouldRun::Yes`] when [`FlyByCameraConfig::active`] is true.
-pub fn is_active(config: Res<FlyByCameraConfig>) -> bool {
-    config.active
+/// Returns `true` when [`InputContext::FreeCamera`] consumes input.
+pub fn is_active(stack: Res<InputContextStack>) -> bool {
+    stack.current() == InputContext::FreeCamera
+}
+
+fn register_bindings(config: Res<FlyByCameraConfig>, mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Toggle free camera", [TOGGLE_KEY]);
+    bindings.set(
+        Some(InputContext::FreeCamera),
+        "Adjust speed",
+        [SingleAxis::mouse_wheel_y()],
+    );
+    bindings.set(
+        Some(InputContext::FreeCamera),
+        "Next camera",
+        [NEXT_CAMERA_KEY],
+    );
+    for action in CameraAction::variants() {
+        bindings.set(
+            Some(InputContext::FreeCamera),
+            format!("{:?}", action),
+            config.bindings.get(action).iter().cloned(),
+        );
+    }
+}
+
+fn toggle_camera(
+    input: Res<Input<KeyCode>>,
+    stack: Res<InputContextStack>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    if input.just_released(TOGGLE_KEY) {
+        toggle_context(InputContext::FreeCamera, &stack, &mut events);
+    }
+}
+
+/// Adds the [`InputMap`] built from [`FlyByCameraConfig::bindings`] to cameras, keeping it up to
+/// date when bindings change.
+fn update_input_map(
+    mut commands: Commands,
+    config: Res<FlyByCameraConfig>,
+    mut q: Query<(Entity, Option<&mut InputMap<CameraAction>>), With<FlyByCamera>>,
+) {
+    for (entity, input_map) in &mut q {
+        match input_map {
+            Some(mut input_map) if config.is_changed() => *input_map = config.bindings.input_map(),
+            Some(_) => (),
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(InputManagerBundle::<CameraAction> {
+                        input_map: config.bindings.input_map(),
+                        ..default()
+                    });
+            }
+        }
+    }
+}
+
+/// Toggles orbiting around the point [`FlyByCameraConfig::orbit_distance`] in front of the
+/// camera.
+fn toggle_orbit(
+    config: Res<FlyByCameraConfig>,
+    mut q: Query<
+        (&Transform, &mut FlyByCamera, &ActionState<CameraAction>),
+        With<ActiveController>,
+    >,
+) {
+    for (transform, mut camera, action_state) in &mut q {
+        if action_state.just_pressed(CameraAction::Orbit) {
+            camera.orbit_focus = match camera.orbit_focus {
+                Some(_) => None,
+                None => Some(transform.translation + transform.forward() * config.orbit_distance),
+            };
+        }
+    }
+}
+
+/// Multiplies or divides the move speed by [`SPEED_STEP`] for each scroll wheel line.
+fn adjust_speed(
+    mut wheel: EventReader<MouseWheel>,
+    mut q: Query<&mut FlyByCamera, With<ActiveController>>,
+) {
+    let lines = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum::<f32>();
+
+    if lines == 0.0 {
+        return;
+    }
+
+    let (min, max) = SPEED_SCALE_RANGE;
+    for mut camera in &mut q {
+        camera.speed_scale = (camera.speed_scale * SPEED_STEP.powf(lines)).clamp(min, max);
+    }
 }
 
 /// Move camera around using [`FlyByCameraConfig`] configuration settings.
 /// This system is gated by [`is_active`] run criteria.
 fn move_camera(
     time: Res<Time>,
-    input: Res<Input<KeyCode>>,
     config: Res<FlyByCameraConfig>,
-    mut q: Query<&mut Transform, With<FlyByCamera>>,
+    mut q: Query<
+        (&mut Transform, &mut FlyByCamera, &ActionState<CameraAction>),
+        With<ActiveController>,
+    >,
 ) {
-    if let Ok(mut transform) = q.get_single_mut() {
-        let input_vector = calc_input_vector(&input, &config.bindings);
+    for (mut transform, mut camera, action_state) in &mut q {
+        let input_vector = calc_input_vector(action_state);
 
-        let speed = if input.pressed(config.bindings.boost) {
-            config.move_speed * config.move_speed_boost
-        } else {
-            config.move_speed
-        };
+        let mut speed = config.move_speed * camera.speed_scale;
+        if action_state.pressed(CameraAction::Boost) {
+            speed *= config.move_speed_boost;
+        }
 
-        if input_vector.length().abs() > 0.0 {
-            let forward_vector = transform.forward() * input_vector.z;
-            let right_vector = transform.right() * input_vector.x;
-            let up_vector = Vec3::Y * input_vector.y;
+        // While orbiting, the focus moves over the ground, no matter where the camera is looking
+        let right_vector = transform.right();
+        let forward_vector = match camera.orbit_focus {
+            Some(_) => Vec3::Y.cross(right_vector),
+            None => transform.forward(),
+        };
 
-            let move_vector = forward_vector + right_vector + up_vector;
+        let target_velocity = speed
+            * (forward_vector * input_vector.z
+                + right_vector * input_vector.x
+                + Vec3::Y * input_vector.y);
 
-            transform.translation += speed * time.delta_seconds() * move_vector;
+        // Exponential smoothing, so it accelerates and stops the same way on any frame rate
+        let rate = if input_vector == Vec3::ZERO {
+            config.damping
+        } else {
+            config.acceleration
+        };
+        let dt = time.delta_seconds();
+        camera.velocity = camera
+            .velocity
+            .lerp(target_velocity, 1.0 - (-rate * dt).exp());
+
+        let offset = camera.velocity * dt;
+        match &mut camera.orbit_focus {
+            Some(focus) => *focus += offset,
+            None => transform.translation += offset,
         }
     }
 }
@@ -141,22 +402,25 @@ fn move_camera(
 /// This system is gated by [`is_active`] run criteria.
 fn rotate_camera(
     time: Res<Time>,
-    mut motion_evt: EventReader<MouseMotion>,
     config: Res<FlyByCameraConfig>,
-    mut q: Query<&mut Transform, With<FlyByCamera>>,
+    mut q: Query<(&mut Transform, &ActionState<CameraAction>), With<ActiveController>>,
 ) {
-    if let Ok(mut transform) = q.get_single_mut() {
-        let mut delta = Vec2::ZERO;
-        for ev in motion_evt.read() {
-            delta += ev.delta;
-        }
+    for (mut transform, action_state) in &mut q {
+        let axis = |action| {
+            action_state
+                .axis_pair(action)
+                .map(|axis| axis.xy())
+                .unwrap_or_default()
+        };
+
+        // Mouse motion is already how much it moved on this frame, while sticks are a rate
+        let delta = axis(CameraAction::Look) * config.look_sensitivity
+            + axis(CameraAction::Turn) * config.turn_speed * time.delta_seconds();
 
         if delta.length().abs() == 0.0 {
-            return;
+            continue;
         }
 
-        delta *= config.rotate_speed * time.delta_seconds();
-
         let (pitch, yaw, _) = transform.rotation.to_euler(EulerRot::YXZ);
         let mut rotation = Vec2::new(pitch, yaw) - delta;
 
@@ -170,44 +434,25 @@ fn rotate_camera(
     }
 }
 
-fn calc_input_vector(input: &Res<Input<KeyCode>>, bindings: &KeyBindings) -> Vec3 {
-    let mut res = Vec3::ZERO;
-
-    if input.pressed(bindings.forward) {
-        res.z += 1.0
-    }
-
-    if input.pressed(bindings.backward) {
-        res.z -= 1.0
-    }
-
-    if input.pressed(bindings.right) {
-        res.x += 1.0
-    }
-
-    if input.pressed(bindings.left) {
-        res.x -= 1.0
+/// Keeps orbiting cameras [`FlyByCameraConfig::orbit_distance`] behind their focus, so rotating
+/// them moves them around it.
+fn orbit_camera(config: Res<FlyByCameraConfig>, mut q: Query<(&mut Transform, &FlyByCamera)>) {
+    for (mut transform, camera) in &mut q {
+        if let Some(focus) = camera.orbit_focus {
+            transform.translation = focus - transform.forward() * config.orbit_distance;
+        }
     }
+}
 
-    if input.pressed(bindings.up) {
-        res.y += 1.0
-    }
+fn calc_input_vector(action_state: &ActionState<CameraAction>) -> Vec3 {
+    let movement = action_state
+        .axis_pair(CameraAction::Move)
+        .map(|axis| axis.xy().clamp_length_max(1.0))
+        .unwrap_or_default();
 
-    if input.pressed(bindings.down) {
-        res.y -= 1.0
-    }
+    // Triggers are analog, so they move slower when partially pressed
+    let vertical = action_state.value(CameraAction::Up).min(1.0)
+        - action_state.value(CameraAction::Down).min(1.0);
 
-    res
-}
-
-fn toggle_mouse_grab(mut windows: Query<&mut Window>, config: Res<FlyByCameraConfig>) {
-    if let Ok(mut window) = windows.get_single_mut() {
-        if config.active {
-            window.cursor.visible = false;
-            window.cursor.grab_mode = bevy::window::CursorGrabMode::Locked;
-        } else {
-            window.cursor.visible = true;
-            window.cursor.grab_mode = bevy::window::CursorGrabMode::None;
-        }
-    }
+    Vec3::new(movement.x, vertical, movement.y)
 }
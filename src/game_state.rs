@@ -0,0 +1,313 @@
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use bevy_inspector_egui::{bevy_egui::EguiContexts, egui};
+
+use crate::{
+    input_context::{InputBindings, InputContext, InputContextEvent, InputContextStack},
+    shutdown::ExitRequested,
+};
+
+/// Key which pauses and resumes the game.
+const PAUSE_KEY: KeyCode = KeyCode::P;
+
+/// Adds [`GameState`], with its main menu, loading screen and pause menu.
+///
+/// Plugins scope their systems to states, report what they load on [`LoadingProgress`] and clean
+/// up the game on [`OnExitGame`].
+///
+/// Requires [`InputContextPlugin`](crate::input_context::InputContextPlugin) and
+/// [`ShutdownPlugin`](crate::shutdown::ShutdownPlugin), which handles the exit buttons.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>()
+            .init_resource::<LoadingProgress>()
+            .init_resource::<PauseMenu>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                Update,
+                (
+                    boot.run_if(in_state(GameState::Boot)),
+                    main_menu.run_if(in_state(GameState::MainMenu)),
+                    (loading_screen, finish_loading)
+                        .chain()
+                        .run_if(in_state(GameState::Loading)),
+                    toggle_pause.run_if(in_game),
+                    (resume_on_back, pause_menu)
+                        .chain()
+                        .run_if(in_state(GameState::Paused)),
+                ),
+            )
+            .add_systems(OnExit(GameState::Loading), reset_loading_progress)
+            .add_systems(OnEnter(GameState::Paused), pause)
+            .add_systems(OnExit(GameState::Paused), (resume, run_on_exit_game))
+            .add_systems(OnExit(GameState::InGame), run_on_exit_game);
+    }
+}
+
+/// Where the game is, from booting up to playing.
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// Starting up, before anything is shown.
+    #[default]
+    Boot,
+    MainMenu,
+    /// Generating the map and spawning it, while a progress bar is shown.
+    Loading,
+    InGame,
+    /// In game, but with time stopped and the pause menu open.
+    Paused,
+}
+
+impl GameState {
+    /// The game is running, even if it's paused.
+    pub fn is_in_game(self) -> bool {
+        matches!(self, GameState::InGame | GameState::Paused)
+    }
+}
+
+/// Run condition which is `true` while [`GameState::is_in_game`].
+pub fn in_game(state: Res<State<GameState>>) -> bool {
+    state.get().is_in_game()
+}
+
+/// Schedule which runs when leaving the game, from [`GameState::InGame`] or [`GameState::Paused`]
+/// to any state outside of it. Entities spawned for the game are despawned here.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OnExitGame;
+
+/// Progress of [`GameState::Loading`], which finishes once every step is done. Plugins set the
+/// `total` steps on [`OnEnter`] and update it as they go.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LoadingProgress {
+    /// What is being loaded, shown over the progress bar.
+    pub stage: String,
+    pub done: usize,
+    pub total: usize,
+}
+
+impl LoadingProgress {
+    /// Progress in range [0, 1].
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.done as f32 / self.total as f32
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.total > 0 && self.done >= self.total
+    }
+}
+
+#[derive(Resource, Default)]
+struct PauseMenu {
+    /// Whether [`InputContext::UI`] was already pushed, so the game is resumed once it's popped.
+    context_pushed: bool,
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Pause", [PAUSE_KEY]);
+}
+
+/// Everything needed to show the menu is set up on [`Startup`], so it goes right to it.
+fn boot(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::MainMenu);
+}
+
+/// Window centered on the screen, used by every menu.
+fn menu_window(title: &str) -> egui::Window<'_> {
+    egui::Window::new(title)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .collapsible(false)
+        .resizable(false)
+}
+
+fn main_menu(
+    mut egui_contexts: EguiContexts,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<ExitRequested>,
+) {
+    menu_window("Malrok").show(egui_contexts.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            if ui.button("Play").clicked() {
+                next_state.set(GameState::Loading);
+            }
+            if ui.button("Exit").clicked() {
+                exit.send(ExitRequested);
+            }
+        });
+    });
+}
+
+fn loading_screen(mut egui_contexts: EguiContexts, progress: Res<LoadingProgress>) {
+    menu_window("Loading").show(egui_contexts.ctx_mut(), |ui| {
+        ui.label(&progress.stage);
+        ui.add(egui::ProgressBar::new(progress.fraction()).show_percentage());
+    });
+}
+
+fn finish_loading(progress: Res<LoadingProgress>, mut next_state: ResMut<NextState<GameState>>) {
+    if progress.is_finished() {
+        next_state.set(GameState::InGame);
+    }
+}
+
+fn reset_loading_progress(mut progress: ResMut<LoadingProgress>) {
+    *progress = default();
+}
+
+/// Pauses while in game and not typing. It resumes while the pause menu is on top, and not under a
+/// dialog.
+fn toggle_pause(
+    input: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    stack: Res<InputContextStack>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !input.just_pressed(PAUSE_KEY) {
+        return;
+    }
+
+    match state.get() {
+        GameState::InGame if stack.current().accepts_shortcuts() => {
+            next_state.set(GameState::Paused)
+        }
+        GameState::Paused if stack.current() == InputContext::UI => {
+            next_state.set(GameState::InGame)
+        }
+        _ => (),
+    }
+}
+
+fn pause(
+    mut time: ResMut<Time<Virtual>>,
+    mut menu: ResMut<PauseMenu>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    time.pause();
+    menu.context_pushed = false;
+    events.send(InputContextEvent::Push(InputContext::UI));
+}
+
+fn resume(mut time: ResMut<Time<Virtual>>, mut events: EventWriter<InputContextEvent>) {
+    time.unpause();
+    events.send(InputContextEvent::Pop(InputContext::UI));
+}
+
+/// Resumes by `Esc`, or anything else which pops the UI context. Dialogs opened over the menu,
+/// like the exit confirmation, use their own context, so closing them doesn't resume.
+fn resume_on_back(
+    mut menu: ResMut<PauseMenu>,
+    stack: Res<InputContextStack>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if stack.contains(InputContext::UI) {
+        menu.context_pushed = true;
+    } else if menu.context_pushed {
+        next_state.set(GameState::InGame);
+    }
+}
+
+fn pause_menu(
+    mut egui_contexts: EguiContexts,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<ExitRequested>,
+) {
+    menu_window("Paused").show(egui_contexts.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            if ui.button("Resume").clicked() {
+                next_state.set(GameState::InGame);
+            }
+            if ui.button("Main menu").clicked() {
+                next_state.set(GameState::MainMenu);
+            }
+            if ui.button("Exit").clicked() {
+                exit.send(ExitRequested);
+            }
+        });
+    });
+}
+
+/// Runs [`OnExitGame`] when the state left the game. State transitions replace the state before
+/// running [`OnExit`], so it's already the new one.
+fn run_on_exit_game(world: &mut World) {
+    if !world.resource::<State<GameState>>().get().is_in_game() {
+        world.try_run_schedule(OnExitGame).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::window::WindowCloseRequested;
+
+    use crate::{
+        input_context::apply_context_events,
+        shutdown::{close_exit_dialog, open_exit_dialog, ExitDialog, Shutdown},
+    };
+
+    use super::*;
+
+    /// App with the pause menu and exit dialog, without drawing them.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_state::<GameState>()
+            .init_resource::<Time<Virtual>>()
+            .init_resource::<InputContextStack>()
+            .init_resource::<PauseMenu>()
+            .init_resource::<Shutdown>()
+            .init_resource::<ExitDialog>()
+            .add_event::<InputContextEvent>()
+            .add_event::<ExitRequested>()
+            .add_event::<WindowCloseRequested>()
+            .add_systems(PreUpdate, apply_context_events)
+            .add_systems(
+                Update,
+                (
+                    resume_on_back.run_if(in_state(GameState::Paused)),
+                    open_exit_dialog,
+                    close_exit_dialog,
+                )
+                    .chain(),
+            )
+            .add_systems(OnEnter(GameState::Paused), pause)
+            .add_systems(OnExit(GameState::Paused), resume);
+        app
+    }
+
+    fn current_context(app: &App) -> InputContext {
+        app.world.resource::<InputContextStack>().current()
+    }
+
+    #[test]
+    fn cancelling_exit_dialog_keeps_game_paused() {
+        let mut app = app();
+
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Paused);
+        app.update();
+        app.update();
+        assert_eq!(current_context(&app), InputContext::UI);
+
+        // Exit from the pause menu
+        app.world.send_event(ExitRequested);
+        app.update();
+        app.update();
+        assert_eq!(current_context(&app), InputContext::Dialog);
+
+        // Cancelled, like by `Esc`
+        app.world
+            .send_event(InputContextEvent::Pop(InputContext::Dialog));
+        app.update();
+        app.update();
+
+        assert_eq!(current_context(&app), InputContext::UI);
+        assert_eq!(
+            app.world.resource::<State<GameState>>().get(),
+            &GameState::Paused
+        );
+        assert!(app.world.resource::<Time<Virtual>>().is_paused());
+    }
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+
+use crate::{
+    map::{MapGenerated, MapGeneration, MapLogicPlugin},
+    player::{self, PlayerLogicPlugin, PlayerUpdate},
+    world_time::WorldTimeLogicPlugin,
+};
+
+/// Runs the game logic without window or renderer: generates the map from
+/// [`MapSettings`](crate::map::MapSettings), advances the world time and spawns a player once the
+/// map is generated, which moves without input.
+///
+/// Requires [`MinimalPlugins`], or any other plugins which add time and task pools.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((MapLogicPlugin, WorldTimeLogicPlugin, PlayerLogicPlugin))
+            .add_systems(
+                Update,
+                player::spawn_logic_player
+                    .run_if(on_event::<MapGenerated>())
+                    .after(MapGeneration)
+                    .before(PlayerUpdate),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map::Heightmap, player::Player};
+
+    use super::*;
+
+    #[test]
+    fn generates_map_and_spawns_player() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, HeadlessPlugin));
+
+        for _ in 0..3 {
+            app.update();
+        }
+
+        let heightmap = app.world.resource::<Heightmap>();
+        assert!(heightmap.width > 0 && heightmap.depth > 0);
+
+        let players = app
+            .world
+            .query_filtered::<&Transform, With<Player>>()
+            .iter(&app.world)
+            .count();
+        assert_eq!(players, 1);
+    }
+}
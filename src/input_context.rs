@@ -0,0 +1,391 @@
+use std::fmt;
+
+use bevy::{
+    input::InputSystem,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+use bevy_inspector_egui::bevy_egui::{EguiContexts, EguiPlugin};
+use leafwing_input_manager::{prelude::*, user_input::InputKind};
+
+/// Adds the [`InputContextStack`], which decides what consumes input, and the [`InputBindings`]
+/// registry, checked for conflicts on [`PostStartup`].
+///
+/// Contexts are changed by sending [`InputContextEvent`]s. The cursor is grabbed while
+/// [`InputContext::FreeCamera`] is on top, `Esc` pops the top context and [`InputContext::UI`]
+/// is pushed while egui is typing.
+///
+/// Controllers, like the fly camera, only move the entity tagged with [`ActiveController`].
+pub struct InputContextPlugin;
+
+impl Plugin for InputContextPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+
+        app.init_resource::<InputContextStack>()
+            .register_type::<ActiveController>()
+            .init_resource::<InputBindings>()
+            .add_event::<InputContextEvent>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(PostStartup, detect_binding_conflicts)
+            .add_systems(
+                PreUpdate,
+                (
+                    sync_ui_context,
+                    pop_on_back,
+                    apply_context_events,
+                    update_cursor_grab.run_if(resource_changed::<InputContextStack>()),
+                )
+                    .chain()
+                    .in_set(InputContextUpdate)
+                    .after(InputSystem),
+            );
+    }
+}
+
+/// [`SystemSet`] where the [`InputContextStack`] is updated, before any [`Update`] system.
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct InputContextUpdate;
+
+/// What input is currently used for. Only the context on top of the [`InputContextStack`]
+/// consumes input.
+///
+/// There is no chat context, since the game has no chat yet. Text fields, like a future chat box,
+/// use [`InputContext::UI`], which already keeps shortcuts from consuming typed keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+pub enum InputContext {
+    /// Controls the player. Always at the bottom of the stack.
+    Gameplay,
+    /// Flies the camera around, with the cursor grabbed.
+    FreeCamera,
+    /// Types on egui widgets.
+    UI,
+    /// Edits the terrain.
+    Editor,
+    /// Answers a dialog opened over any other context, like the exit confirmation, so closing it
+    /// doesn't close the menu below it.
+    Dialog,
+}
+
+impl InputContext {
+    /// Cursor is hidden and locked to the window, so mouse motion can be used to look around.
+    pub fn grabs_cursor(self) -> bool {
+        self == InputContext::FreeCamera
+    }
+
+    /// Contexts used to type text or answer a dialog don't let global shortcuts, like debug
+    /// toggles, through.
+    pub fn accepts_shortcuts(self) -> bool {
+        !matches!(self, InputContext::UI | InputContext::Dialog)
+    }
+}
+
+/// Pushes or pops an [`InputContext`] from the [`InputContextStack`].
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputContextEvent {
+    /// Puts the context on top of the stack, moving it there if it's already on the stack.
+    Push(InputContext),
+    /// Removes the context from the stack, even if it isn't on top.
+    Pop(InputContext),
+}
+
+/// Stack of active [`InputContext`]s, where [`InputContext::Gameplay`] is always at the bottom and
+/// can't be pushed or popped.
+#[derive(Resource, Debug, Default)]
+pub struct InputContextStack(Vec<InputContext>);
+
+impl InputContextStack {
+    /// Context which consumes input.
+    pub fn current(&self) -> InputContext {
+        self.0.last().copied().unwrap_or(InputContext::Gameplay)
+    }
+
+    pub fn contains(&self, context: InputContext) -> bool {
+        context == InputContext::Gameplay || self.0.contains(&context)
+    }
+
+    fn push(&mut self, context: InputContext) {
+        if context != InputContext::Gameplay {
+            self.0.retain(|&c| c != context);
+            self.0.push(context);
+        }
+    }
+
+    fn pop(&mut self, context: InputContext) {
+        self.0.retain(|&c| c != context);
+    }
+}
+
+/// Run condition which is `true` when the given `context` consumes input.
+pub fn in_context(context: InputContext) -> impl FnMut(Res<InputContextStack>) -> bool + Clone {
+    move |stack: Res<InputContextStack>| stack.current() == context
+}
+
+/// Run condition for global shortcuts, which is `false` while typing.
+pub fn accepts_shortcuts(stack: Res<InputContextStack>) -> bool {
+    stack.current().accepts_shortcuts()
+}
+
+/// Sends an event which pushes `context` when it isn't on top, or pops it otherwise.
+pub fn toggle_context(
+    context: InputContext,
+    stack: &InputContextStack,
+    events: &mut EventWriter<InputContextEvent>,
+) {
+    if stack.current() == context {
+        events.send(InputContextEvent::Pop(context));
+    } else {
+        events.send(InputContextEvent::Push(context));
+    }
+}
+
+/// Tags the entity moved by a controller, among every entity with the controller component, like
+/// [`FlyByCamera`](crate::fly_by_cam::FlyByCamera). There can be many controlled entities, like
+/// split screen cameras or local test players, but only the active one consumes input.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy)]
+#[reflect(Component)]
+pub struct ActiveController;
+
+/// Keeps a single entity with `T` tagged as [`ActiveController`], picking the first one when
+/// there is none, like after the active one is despawned.
+pub fn ensure_active_controller<T: Component>(
+    mut commands: Commands,
+    q: Query<(Entity, Has<ActiveController>), With<T>>,
+) {
+    let mut active = q
+        .iter()
+        .filter(|&(_, active)| active)
+        .map(|(entity, _)| entity);
+
+    match active.next() {
+        Some(_) => {
+            for entity in active {
+                commands.entity(entity).remove::<ActiveController>();
+            }
+        }
+        None => {
+            if let Some(entity) = q.iter().map(|(entity, _)| entity).min() {
+                commands.entity(entity).insert(ActiveController);
+            }
+        }
+    }
+}
+
+/// Moves [`ActiveController`] to the next entity with `T`, in entity order.
+pub fn cycle_active_controller<T: Component>(
+    mut commands: Commands,
+    q: Query<(Entity, Has<ActiveController>), With<T>>,
+) {
+    let mut entities = q.iter().collect::<Vec<_>>();
+    entities.sort_by_key(|&(entity, _)| entity);
+
+    let Some(index) = entities.iter().position(|&(_, active)| active) else {
+        return;
+    };
+    let (current, _) = entities[index];
+    let (next, _) = entities[(index + 1) % entities.len()];
+
+    if next != current {
+        commands.entity(current).remove::<ActiveController>();
+        commands.entity(next).insert(ActiveController);
+    }
+}
+
+/// Inputs bound to an action, available on `context` or on every context when it's `None`.
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub context: Option<InputContext>,
+    pub action: String,
+    pub inputs: Vec<UserInput>,
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.context {
+            Some(context) => write!(f, "{} ({:?})", self.action, context),
+            None => write!(f, "{} (global)", self.action),
+        }
+    }
+}
+
+/// Two bindings which are triggered by the same input on the same context.
+#[derive(Debug, Clone)]
+pub struct BindingConflict {
+    pub input: UserInput,
+    pub first: Binding,
+    pub second: Binding,
+}
+
+impl fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is bound to both {} and {}",
+            self.input, self.first, self.second
+        )
+    }
+}
+
+/// Every input binding of the game, used to detect conflicts between them. Plugins register their
+/// bindings on [`Startup`].
+#[derive(Resource, Debug, Default)]
+pub struct InputBindings(Vec<Binding>);
+
+/// Buttons and axes an input is made of.
+fn input_kinds(input: &UserInput) -> Vec<InputKind> {
+    match input {
+        UserInput::Single(kind) => vec![*kind],
+        UserInput::Chord(kinds) => kinds.iter().copied().collect(),
+        UserInput::VirtualDPad(dpad) => vec![dpad.up, dpad.down, dpad.left, dpad.right],
+        UserInput::VirtualAxis(axis) => vec![axis.negative, axis.positive],
+    }
+}
+
+/// Inputs overlap when they share any button, since pressing one also triggers the other, like a
+/// chord and any input it contains. Chords are told apart from each other by their modifiers, so
+/// they only overlap with the same chord.
+fn overlaps(a: &UserInput, b: &UserInput) -> bool {
+    match (a, b) {
+        (UserInput::Chord(_), UserInput::Chord(_)) => a == b,
+        _ => {
+            let b_kinds = input_kinds(b);
+            input_kinds(a).iter().any(|kind| b_kinds.contains(kind))
+        }
+    }
+}
+
+impl InputBindings {
+    /// Binds `inputs` to `action` on `context`, replacing its previous inputs.
+    pub fn set(
+        &mut self,
+        context: Option<InputContext>,
+        action: impl ToString,
+        inputs: impl IntoIterator<Item = impl Into<UserInput>>,
+    ) {
+        let action = action.to_string();
+        let inputs = inputs.into_iter().map(Into::into).collect();
+
+        match self
+            .0
+            .iter_mut()
+            .find(|binding| binding.context == context && binding.action == action)
+        {
+            Some(binding) => binding.inputs = inputs,
+            None => self.0.push(Binding {
+                context,
+                action,
+                inputs,
+            }),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Binding> {
+        self.0.iter()
+    }
+
+    /// Finds bindings which share an input on the same context, where global bindings share
+    /// every context.
+    pub fn conflicts(&self) -> Vec<BindingConflict> {
+        let mut conflicts = vec![];
+
+        for (i, first) in self.0.iter().enumerate() {
+            for second in &self.0[i + 1..] {
+                let same_context = first.context.is_none()
+                    || second.context.is_none()
+                    || first.context == second.context;
+                if !same_context {
+                    continue;
+                }
+
+                for input in &first.inputs {
+                    if second.inputs.iter().any(|other| overlaps(input, other)) {
+                        conflicts.push(BindingConflict {
+                            input: input.clone(),
+                            first: first.clone(),
+                            second: second.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        conflicts
+    }
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    for context in [
+        InputContext::FreeCamera,
+        InputContext::UI,
+        InputContext::Editor,
+        InputContext::Dialog,
+    ] {
+        bindings.set(Some(context), "Back", [KeyCode::Escape]);
+    }
+}
+
+fn detect_binding_conflicts(bindings: Res<InputBindings>) {
+    for conflict in bindings.conflicts() {
+        warn!("Input conflict: {}", conflict);
+    }
+}
+
+/// Pushes [`InputContext::UI`] while egui is typing, so keys aren't consumed by anything else.
+/// It's only popped if it was pushed here, so menus can keep it on the stack.
+fn sync_ui_context(
+    mut egui_contexts: EguiContexts,
+    stack: Res<InputContextStack>,
+    mut events: EventWriter<InputContextEvent>,
+    mut pushed: Local<bool>,
+) {
+    let typing = egui_contexts.ctx_mut().wants_keyboard_input();
+
+    if typing && stack.current() != InputContext::UI {
+        events.send(InputContextEvent::Push(InputContext::UI));
+        *pushed = true;
+    } else if !typing && *pushed {
+        events.send(InputContextEvent::Pop(InputContext::UI));
+        *pushed = false;
+    }
+}
+
+fn pop_on_back(
+    input: Res<Input<KeyCode>>,
+    stack: Res<InputContextStack>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    if input.just_released(KeyCode::Escape) && stack.current() != InputContext::Gameplay {
+        events.send(InputContextEvent::Pop(stack.current()));
+    }
+}
+
+pub(crate) fn apply_context_events(
+    mut stack: ResMut<InputContextStack>,
+    mut events: EventReader<InputContextEvent>,
+) {
+    for event in events.read() {
+        match *event {
+            InputContextEvent::Push(context) => stack.push(context),
+            InputContextEvent::Pop(context) => stack.pop(context),
+        }
+    }
+}
+
+fn update_cursor_grab(
+    stack: Res<InputContextStack>,
+    mut q_window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let Ok(mut window) = q_window.get_single_mut() else {
+        return;
+    };
+
+    if stack.current().grabs_cursor() {
+        window.cursor.visible = false;
+        window.cursor.grab_mode = CursorGrabMode::Locked;
+    } else {
+        window.cursor.visible = true;
+        window.cursor.grab_mode = CursorGrabMode::None;
+    }
+}
@@ -0,0 +1,28 @@
+#[cfg(feature = "dev-tools")]
+pub mod camera_path;
+pub mod config;
+pub mod controls_menu;
+#[cfg(feature = "dev-tools")]
+pub mod debug_tools;
+pub mod fly_by_cam;
+pub mod game_state;
+pub mod headless;
+pub mod input_context;
+pub mod map;
+pub mod player;
+pub mod rng;
+pub mod shutdown;
+pub mod weather;
+pub mod world_time;
+
+use bevy::prelude::*;
+
+use input_context::ActiveController;
+
+/// Tags cameras which render the game, like the picture in picture one.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct MainCamera;
+
+/// Query filter of the [`MainCamera`] tagged with [`ActiveController`], which follows the player
+/// and is followed by weather and editor tools.
+pub type ActiveCamera = (With<MainCamera>, With<ActiveController>);
@@ -1,77 +1,308 @@
+use std::{process::ExitCode, time::Duration};
+
 use bevy::{
-    app::AppExit,
+    app::ScheduleRunnerPlugin,
+    log::LogPlugin,
     prelude::*,
     render::{
+        camera::Viewport,
         settings::{Backends, WgpuFeatures, WgpuSettings},
         RenderPlugin,
     },
+    window::PrimaryWindow,
     DefaultPlugins,
 };
+#[cfg(feature = "dev-tools")]
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+#[cfg(feature = "dev-tools")]
+use malrok::debug_tools::DebugToolsPlugin;
+use malrok::{
+    config::ClientConfig,
+    controls_menu::ControlsMenuPlugin,
+    fly_by_cam,
+    game_state::{GameStatePlugin, OnExitGame},
+    headless::HeadlessPlugin,
+    input_context::{
+        in_context, InputBindings, InputContext, InputContextEvent, InputContextPlugin,
+    },
+    map, player,
+    shutdown::{ExitRequested, ShutdownPlugin},
+    weather, world_time, MainCamera,
+};
+
+/// Features the game prefers to have, used by debug tools like wireframes.
+#[cfg(feature = "dev-tools")]
+const FEATURES: WgpuFeatures = WgpuFeatures::POLYGON_MODE_LINE;
+#[cfg(not(feature = "dev-tools"))]
+const FEATURES: WgpuFeatures = WgpuFeatures::empty();
+
+/// How often the game logic is updated when running headless.
+const HEADLESS_UPDATE_RATE: f64 = 60.0;
+
+/// Portion of the window size, on each axis, covered by the picture in picture camera.
+const PICTURE_IN_PICTURE_SCALE: u32 = 4;
+
+fn main() -> ExitCode {
+    // Logs aren't available until the app is built, so they are kept until startup
+    let mut warnings = vec![];
+
+    let (config, map_settings) = match ClientConfig::load(&mut warnings).and_then(|config| {
+        config
+            .map_settings()
+            .map(|map_settings| (config, map_settings))
+    }) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let wgpu_settings = if config.headless {
+        None
+    } else {
+        pick_wgpu_settings(config.backend.backends(), &mut warnings)
+    };
+
+    let mut app = App::new();
+    // Inserted before plugins, so they aren't replaced by defaults
+    if let Some(map_settings) = map_settings {
+        app.insert_resource(map_settings);
+    }
+
+    match wgpu_settings {
+        Some(wgpu_settings) => add_client_plugins(&mut app, &config, wgpu_settings),
+        None => add_headless_plugins(&mut app),
+    };
+
+    app.add_systems(Startup, move || {
+        for warning in &warnings {
+            warn!("{}", warning);
+        }
+    })
+    .insert_resource(config)
+    .run();
+
+    ExitCode::SUCCESS
+}
+
+/// Picks the preferred backends and features, falling back to whatever the GPU supports, or
+/// `None` when there is no GPU at all.
+fn pick_wgpu_settings(backends: Backends, warnings: &mut Vec<String>) -> Option<WgpuSettings> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: Backends::all(),
+        ..default()
+    });
+    let adapters = instance
+        .enumerate_adapters(Backends::all())
+        .collect::<Vec<_>>();
+
+    let adapter = match adapters
+        .iter()
+        .find(|adapter| backends.contains(adapter.get_info().backend.into()))
+    {
+        Some(adapter) => adapter,
+        None => {
+            let Some(adapter) = adapters.first() else {
+                warnings.push("No GPU was found, running headless".to_string());
+                return None;
+            };
+            warnings.push(format!(
+                "{:?} isn't available, using {:?} instead",
+                backends,
+                adapter.get_info().backend
+            ));
+            adapter
+        }
+    };
+
+    let features = adapter.features() & FEATURES;
+    if features != FEATURES {
+        warnings.push(format!(
+            "{:?} isn't available, some debug tools won't work",
+            FEATURES - features
+        ));
+    }
 
-mod fly_by_cam;
-mod map;
-mod player;
-
-fn main() {
-    App::new()
-        .add_plugins(
-            DefaultPlugins.set(RenderPlugin {
-                render_creation: WgpuSettings {
-                    backends: Some(Backends::VULKAN),
-                    features: WgpuFeatures::POLYGON_MODE_LINE,
-                    ..default()
-                }
-                .into(),
+    Some(WgpuSettings {
+        backends: Some(adapter.get_info().backend.into()),
+        features,
+        ..default()
+    })
+}
+
+fn add_client_plugins(app: &mut App, config: &ClientConfig, wgpu_settings: WgpuSettings) {
+    app.add_plugins(
+        DefaultPlugins
+            .set(RenderPlugin {
+                render_creation: wgpu_settings.into(),
+            })
+            .set(WindowPlugin {
+                primary_window: Some(config.window.window()),
+                // Closing is confirmed by `ShutdownPlugin`, which lets plugins save first
+                close_when_requested: false,
+                ..default()
             }),
-        )
-        .add_plugins(WorldInspectorPlugin::new())
-        .add_plugins((
-            fly_by_cam::FlyByCameraPlugin,
-            map::MapPlugin,
-            // player::PlayerPlugin,
-        ))
-        .add_systems(Update, (hold_esc_to_exit, toggle_camera))
-        .add_systems(Startup, setup_camera)
-        .run();
+    )
+    .insert_resource(fly_by_cam::FlyByCameraConfig {
+        bindings: config.controls.camera.clone(),
+        ..default()
+    })
+    .insert_resource(player::PlayerControllerConfig {
+        bindings: config.controls.player.clone(),
+        camera_offset: config.camera_position,
+        test_players: config.debug.test_players,
+    })
+    .insert_resource(map::WorldObjectsConfig {
+        debug_grid: config.debug.obstacle_grid,
+        ..default()
+    });
+
+    app.add_plugins((
+        InputContextPlugin,
+        ShutdownPlugin,
+        GameStatePlugin,
+        ControlsMenuPlugin,
+        fly_by_cam::FlyByCameraPlugin,
+        map::MapPlugin,
+        world_time::WorldTimePlugin,
+        weather::WeatherPlugin,
+        player::PlayerPlugin,
+    ))
+    .add_systems(
+        Update,
+        (
+            hold_esc_to_exit.run_if(in_context(InputContext::Gameplay)),
+            apply_fov.run_if(resource_changed::<ClientConfig>()),
+            update_picture_in_picture,
+        ),
+    )
+    .add_systems(Startup, (setup_camera, register_bindings))
+    // Menus need the cursor, so stop flying around when leaving the game
+    .add_systems(OnExitGame, |mut events: EventWriter<InputContextEvent>| {
+        events.send(InputContextEvent::Pop(InputContext::FreeCamera));
+    });
+
+    #[cfg(feature = "dev-tools")]
+    {
+        let mut debug_tools = DebugToolsPlugin.build();
+        if !config.debug.world_inspector {
+            debug_tools = debug_tools.disable::<WorldInspectorPlugin>();
+        }
+        app.add_plugins(debug_tools);
+    }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
-struct MainCamera;
+/// Runs only the game logic, without window or renderer, so it works on machines without a GPU.
+fn add_headless_plugins(app: &mut App) {
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / HEADLESS_UPDATE_RATE,
+        ))),
+        LogPlugin::default(),
+    ))
+    .add_plugins(HeadlessPlugin)
+    .add_systems(Startup, || info!("Running headless"));
+}
+
+/// Tags the camera drawn over a corner of the window, spawned by
+/// [`DebugConfig::picture_in_picture`](malrok::config::DebugConfig::picture_in_picture).
+#[derive(Component)]
+struct PictureInPicture;
 
-fn setup_camera(mut commands: Commands) {
-    commands.spawn((
-        Camera3dBundle {
-            transform: Transform::from_xyz(0.0, 6.0, 12.0).looking_at(Vec3::Y, Vec3::Y),
+fn setup_camera(mut commands: Commands, config: Res<ClientConfig>) {
+    let camera = |order| Camera3dBundle {
+        camera: Camera { order, ..default() },
+        transform: Transform::from_translation(config.camera_position).looking_at(Vec3::Y, Vec3::Y),
+        projection: PerspectiveProjection {
+            fov: config.fov.to_radians(),
             ..default()
-        },
-        fly_by_cam::FlyByCamera,
-        MainCamera,
-    ));
+        }
+        .into(),
+        ..default()
+    };
+
+    // First spawned, so it's the active one
+    commands.spawn((camera(0), fly_by_cam::FlyByCamera::default(), MainCamera));
+
+    if config.debug.picture_in_picture {
+        commands.spawn((
+            camera(1),
+            fly_by_cam::FlyByCamera::default(),
+            MainCamera,
+            PictureInPicture,
+        ));
+    }
 }
 
-fn toggle_camera(
-    mut cam_config: ResMut<fly_by_cam::FlyByCameraConfig>,
-    input: Res<Input<KeyCode>>,
+/// Keeps the [`PictureInPicture`] camera on the bottom right corner of the window.
+fn update_picture_in_picture(
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    mut q_camera: Query<&mut Camera, With<PictureInPicture>>,
 ) {
-    if input.just_released(KeyCode::Escape) {
-        cam_config.active = !cam_config.active;
+    let Ok(window) = q_window.get_single() else {
+        return;
+    };
+
+    let window_size = UVec2::new(window.physical_width(), window.physical_height());
+    let size = window_size / PICTURE_IN_PICTURE_SCALE;
+    let position = window_size - size;
+    // Minimized windows have no size, which isn't a valid viewport
+    let visible = size.cmpgt(UVec2::ZERO).all();
+
+    for mut camera in &mut q_camera {
+        if camera.is_active != visible {
+            camera.is_active = visible;
+        }
+
+        let unchanged = camera.viewport.as_ref().is_some_and(|viewport| {
+            viewport.physical_position == position && viewport.physical_size == size
+        });
+        if visible && !unchanged {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
+
+/// Keeps the camera field of view in sync with [`ClientConfig::fov`].
+fn apply_fov(config: Res<ClientConfig>, mut q_camera: Query<&mut Projection, With<MainCamera>>) {
+    for mut projection in &mut q_camera {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            let fov = config.fov.to_radians();
+            if perspective.fov != fov {
+                perspective.fov = fov;
+            }
+        }
     }
 }
 
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(
+        Some(InputContext::Gameplay),
+        "Exit (hold)",
+        [KeyCode::Escape],
+    );
+}
+
+/// Asks to exit when `Esc` is released after being held. It waits for the release, otherwise it
+/// would also close the confirmation dialog, like any other context popped by `Esc`.
 fn hold_esc_to_exit(
     input: Res<Input<KeyCode>>,
     time: Res<Time>,
     mut accum_hold: Local<f32>,
-    mut exit_evt_writer: EventWriter<AppExit>,
+    mut exit_evt_writer: EventWriter<ExitRequested>,
 ) {
     if input.pressed(KeyCode::Escape) {
         *accum_hold += time.delta_seconds();
-    } else {
-        *accum_hold = 0.0;
+        return;
     }
 
-    if *accum_hold >= 0.5 {
-        exit_evt_writer.send(AppExit);
+    if input.just_released(KeyCode::Escape) && *accum_hold >= 0.5 {
+        exit_evt_writer.send(ExitRequested);
     }
+    *accum_hold = 0.0;
 }
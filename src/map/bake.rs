@@ -0,0 +1,211 @@
+use std::{f32::consts::TAU, ops::Range};
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use serde::Deserialize;
+
+use super::{
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    mesher, TerrainChunk, CHUNK_SIZE,
+};
+
+/// How terrain detail maps are baked from the heightmap.
+#[derive(Reflect, InspectorOptions, Debug, Clone, PartialEq, Deserialize)]
+#[reflect(InspectorOptions, Default)]
+#[serde(default)]
+pub struct BakeSettings {
+    pub enabled: bool,
+    // How far, in cells, the terrain is searched for occluders
+    #[inspector(min = 1, max = 64)]
+    pub ao_radius: u16,
+    // How many directions the terrain is searched for occluders
+    #[inspector(min = 1, max = 32)]
+    pub ao_directions: u32,
+    // How dark fully occluded terrain is, in range [0, 1]
+    #[inspector(min = 0.0, max = 1.0)]
+    pub ao_strength: f32,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ao_radius: 16,
+            ao_directions: 8,
+            ao_strength: 1.0,
+        }
+    }
+}
+
+/// Images baked from the current [`Heightmap`] and used by the terrain material.
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainMaps {
+    pub settings: BakeSettings,
+    pub normal_map: Handle<Image>,
+    pub occlusion: Handle<Image>,
+}
+
+/// Normal of the terrain mesh quad which covers the cell `x`, `z`, computed the same way the
+/// mesher does. Heightmaps narrower than two cells have no quads, so they are flat.
+fn mesh_normal(heightmap: &Heightmap, x: u16, z: u16) -> Vec3 {
+    if heightmap.width < 2 || heightmap.depth < 2 {
+        return Vec3::Y;
+    }
+
+    let x0 = x.min(heightmap.width - 2);
+    let z0 = z.min(heightmap.depth - 2);
+    let (x1, z1) = (x0 + 1, z0 + 1);
+
+    let vertex = |x, z| Vec3::new(x as f32, heightmap.get(x, z) * HEIGHT_SCALE, z as f32);
+    let v0 = vertex(x0, z0);
+
+    (vertex(x0, z1) - v0).cross(vertex(x1, z0) - v0).normalize()
+}
+
+fn normal_texel(heightmap: &Heightmap, x: u16, z: u16) -> [u8; 4] {
+    let normal = mesh_normal(heightmap, x, z);
+    let tangent = mesher::tangent(normal);
+    let bitangent = tangent.cross(normal);
+
+    let detail = heightmap.normal(x, z);
+    let encoded = Vec3::new(
+        detail.dot(tangent),
+        detail.dot(bitangent),
+        detail.dot(normal),
+    ) * 0.5
+        + 0.5;
+
+    let [r, g, b] = encoded
+        .to_array()
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+    [r, g, b, 255]
+}
+
+/// Horizon based ambient occlusion: the higher the terrain around a cell rises above the horizon,
+/// the less ambient light reaches it.
+fn occlusion_texel(heightmap: &Heightmap, x: u16, z: u16, settings: &BakeSettings) -> [u8; 4] {
+    let origin = Vec2::new(x as f32, z as f32);
+    let height = heightmap.get(x, z) * HEIGHT_SCALE;
+    let max = Vec2::new((heightmap.width - 1) as f32, (heightmap.depth - 1) as f32);
+
+    let directions = settings.ao_directions.max(1);
+    let occlusion = (0..directions)
+        .map(|i| {
+            let angle = i as f32 / directions as f32 * TAU;
+            let direction = Vec2::new(angle.cos(), angle.sin());
+
+            let horizon = (1..=settings.ao_radius)
+                .map(|distance| (distance as f32, origin + direction * distance as f32))
+                .take_while(|(_, point)| point.cmpge(Vec2::ZERO).all() && point.cmple(max).all())
+                .map(|(distance, point)| {
+                    (heightmap.sample(point.x, point.y) * HEIGHT_SCALE - height) / distance
+                })
+                .fold(0.0f32, f32::max);
+
+            // Sine of the horizon elevation angle
+            horizon / (1.0 + horizon * horizon).sqrt()
+        })
+        .sum::<f32>()
+        / directions as f32;
+
+    let value = ((1.0 - occlusion * settings.ao_strength).clamp(0.0, 1.0) * 255.0) as u8;
+    [value, value, value, 255]
+}
+
+fn bake_region(
+    image: &mut Image,
+    heightmap: &Heightmap,
+    x_range: Range<u16>,
+    z_range: Range<u16>,
+    texel: impl Fn(u16, u16) -> [u8; 4],
+) {
+    for z in z_range {
+        for x in x_range.clone() {
+            let index = (z as usize * heightmap.width as usize + x as usize) * 4;
+            image.data[index..index + 4].copy_from_slice(&texel(x, z));
+        }
+    }
+}
+
+fn bake(heightmap: &Heightmap, texel: impl Fn(u16, u16) -> [u8; 4]) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: heightmap.width as u32,
+            height: heightmap.depth as u32,
+            ..default()
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        // Detail maps hold data, not colors, so they must not be gamma corrected
+        TextureFormat::Rgba8Unorm,
+    );
+    bake_region(
+        &mut image,
+        heightmap,
+        0..heightmap.width,
+        0..heightmap.depth,
+        texel,
+    );
+    image
+}
+
+/// Bakes a tangent space normal map, which smooths the lighting of the terrain mesh quads with the
+/// normals of the heightmap.
+pub fn bake_normal_map(heightmap: &Heightmap) -> Image {
+    bake(heightmap, |x, z| normal_texel(heightmap, x, z))
+}
+
+/// Bakes an ambient occlusion map, which darkens valleys and crevices of the terrain.
+pub fn bake_ambient_occlusion(heightmap: &Heightmap, settings: &BakeSettings) -> Image {
+    bake(heightmap, |x, z| occlusion_texel(heightmap, x, z, settings))
+}
+
+/// Bakes again the part of the terrain maps covered by chunks edited on the terrain editor.
+pub fn rebake_edited_chunks(
+    mut images: ResMut<Assets<Image>>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    q_chunks: Query<(&Handle<Mesh>, &TerrainChunk)>,
+    heightmap: Res<Heightmap>,
+    terrain_maps: Option<Res<TerrainMaps>>,
+) {
+    let Some(terrain_maps) = terrain_maps else {
+        return;
+    };
+
+    for event in mesh_events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        let Some((_, chunk)) = q_chunks.iter().find(|(handle, _)| handle.id() == *id) else {
+            continue;
+        };
+
+        let settings = &terrain_maps.settings;
+        // Occlusion of cells around the chunk may also have changed
+        let margin = settings.ao_radius + 1;
+        let x_range = (chunk.x * CHUNK_SIZE).saturating_sub(margin)
+            ..((chunk.x + 1) * CHUNK_SIZE + margin).min(heightmap.width);
+        let z_range = (chunk.z * CHUNK_SIZE).saturating_sub(margin)
+            ..((chunk.z + 1) * CHUNK_SIZE + margin).min(heightmap.depth);
+
+        if let Some(image) = images.get_mut(&terrain_maps.normal_map) {
+            bake_region(
+                image,
+                &heightmap,
+                x_range.clone(),
+                z_range.clone(),
+                |x, z| normal_texel(&heightmap, x, z),
+            );
+        }
+
+        if let Some(image) = images.get_mut(&terrain_maps.occlusion) {
+            bake_region(image, &heightmap, x_range, z_range, |x, z| {
+                occlusion_texel(&heightmap, x, z, settings)
+            });
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use libnoise::{Generator, Source};
+
+use super::{
+    heightmap::Heightmap,
+    hydrology::Hydrology,
+    water::{WaterKind, WaterMap},
+};
+
+/// Height, in heightmap units, above which everything is covered by snow.
+const SNOW_LINE: f32 = 0.8;
+
+/// Height, in heightmap units, above which terrain becomes bare rock.
+const MOUNTAIN_LINE: f32 = 0.65;
+
+/// Slope, as rise over run, above which terrain is too steep for anything else than rock.
+const CLIFF_SLOPE: f32 = 1.5;
+
+/// Amount of cells draining into a cell which makes it fully moist.
+const RIVER_MOISTURE_ACCUMULATION: f32 = 500.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+pub enum Biome {
+    #[default]
+    Water,
+    Beach,
+    Desert,
+    Grassland,
+    Forest,
+    Mountain,
+    Snow,
+}
+
+impl Biome {
+    /// Color used to paint terrain and maps.
+    pub fn color(self) -> Color {
+        match self {
+            Biome::Water => Color::rgb(0.2, 0.35, 0.55),
+            Biome::Beach => Color::rgb(0.86, 0.8, 0.6),
+            Biome::Desert => Color::rgb(0.8, 0.7, 0.45),
+            Biome::Grassland => Color::rgb(0.45, 0.65, 0.3),
+            Biome::Forest => Color::rgb(0.2, 0.45, 0.2),
+            Biome::Mountain => Color::rgb(0.5, 0.47, 0.45),
+            Biome::Snow => Color::rgb(0.95, 0.95, 0.97),
+        }
+    }
+}
+
+/// Holds the [`Biome`] of each cell of the final [`Heightmap`].
+#[derive(Resource, Debug, Default, Clone)]
+pub struct BiomeMap {
+    width: u16,
+    depth: u16,
+    cells: Vec<Biome>,
+}
+
+impl BiomeMap {
+    /// Classifies every cell of `heightmap` using its height, slope, water and a moisture noise
+    /// generated from `seed`. Cells close to rivers are moister than the rest.
+    pub fn new(
+        heightmap: &Heightmap,
+        hydrology: &Hydrology,
+        water_map: &WaterMap,
+        seed: u64,
+    ) -> Self {
+        let mut biome_map = Self {
+            width: heightmap.width,
+            depth: heightmap.depth,
+            cells: vec![Biome::default(); heightmap.buffer_size()],
+        };
+
+        let cells = (0..heightmap.buffer_size()).map(|index| heightmap.position(index));
+        biome_map.update(heightmap, hydrology, water_map, seed, cells);
+
+        biome_map
+    }
+
+    /// Classifies the given `cells` again, like after the terrain is edited, the same way
+    /// [`BiomeMap::new`] does.
+    pub fn update(
+        &mut self,
+        heightmap: &Heightmap,
+        hydrology: &Hydrology,
+        water_map: &WaterMap,
+        seed: u64,
+        cells: impl IntoIterator<Item = [u16; 2]>,
+    ) {
+        let moisture_noise = Source::simplex(seed).fbm(3, 4.0, 2.0, 0.5);
+
+        for [x, z] in cells {
+            let index = heightmap.index(x, z);
+            let height = heightmap[index];
+
+            let noise = moisture_noise.sample([
+                x as f64 / heightmap.width as f64,
+                z as f64 / heightmap.depth as f64,
+            ]);
+            let river = (hydrology.flow_accumulation(index) / RIVER_MOISTURE_ACCUMULATION).min(1.0);
+            let moisture = ((noise as f32 + 1.0) / 2.0).max(river);
+
+            let biome = match water_map.get(x, z) {
+                WaterKind::Shallow | WaterKind::Deep => Biome::Water,
+                WaterKind::Shore => Biome::Beach,
+                WaterKind::Land if height >= SNOW_LINE => Biome::Snow,
+                WaterKind::Land
+                    if height >= MOUNTAIN_LINE || heightmap.slope(x, z) >= CLIFF_SLOPE =>
+                {
+                    Biome::Mountain
+                }
+                WaterKind::Land if moisture < 0.3 => Biome::Desert,
+                WaterKind::Land if moisture < 0.6 => Biome::Grassland,
+                WaterKind::Land => Biome::Forest,
+            };
+            self.set(x, z, biome);
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u16 {
+        self.depth
+    }
+
+    pub fn get(&self, x: u16, z: u16) -> Biome {
+        self.cells[x as usize * self.depth as usize + z as usize]
+    }
+
+    pub fn set(&mut self, x: u16, z: u16, biome: Biome) {
+        self.cells[x as usize * self.depth as usize + z as usize] = biome;
+    }
+}
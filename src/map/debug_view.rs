@@ -0,0 +1,213 @@
+use bevy::{pbr::wireframe::Wireframe, prelude::*, render::renderer::RenderDevice};
+
+use crate::{
+    debug_tools,
+    input_context::{accepts_shortcuts, InputBindings},
+    ActiveCamera,
+};
+
+use super::{
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    water::{WaterKind, WaterMap},
+    TerrainChunk, CHUNK_SIZE,
+};
+
+const TERRAIN_WIREFRAME_KEY: KeyCode = KeyCode::F4;
+const NORMALS_KEY: KeyCode = KeyCode::F5;
+const CHUNK_BORDERS_KEY: KeyCode = KeyCode::F6;
+const NAVIGATION_GRID_KEY: KeyCode = KeyCode::F7;
+
+/// Only cells closer than this to the camera have their normals and navigation grid drawn.
+const VIEW_DISTANCE: f32 = 32.0;
+
+/// Length, in world units, of the normal lines.
+const NORMAL_LENGTH: f32 = 1.0;
+
+/// How high above the terrain chunk borders and the navigation grid are drawn, so they aren't
+/// hidden by it.
+const SURFACE_OFFSET: f32 = 0.1;
+
+/// Color of the chunk borders.
+const CHUNK_BORDER_COLOR: Color = Color::YELLOW;
+
+/// Adds hotkeys which toggle debug visualizations of the terrain, all drawn with gizmos except
+/// the wireframe:
+///
+/// - `F4`: terrain wireframe;
+/// - `F5`: terrain normals;
+/// - `F6`: chunk borders;
+/// - `F7`: navigation grid, colored by [`WaterKind`].
+pub struct TerrainDebugPlugin;
+
+impl Plugin for TerrainDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainDebugConfig>()
+            .register_type::<TerrainDebugConfig>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                Update,
+                (
+                    toggle_debug_views.run_if(accepts_shortcuts),
+                    update_wireframes,
+                    draw_normals.run_if(|config: Res<TerrainDebugConfig>| config.normals),
+                    draw_chunk_borders
+                        .run_if(|config: Res<TerrainDebugConfig>| config.chunk_borders),
+                    draw_navigation_grid
+                        .run_if(|config: Res<TerrainDebugConfig>| config.navigation_grid),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Which terrain debug visualizations are shown.
+#[derive(Resource, Reflect, Debug, Default, Clone)]
+#[reflect(Resource)]
+pub struct TerrainDebugConfig {
+    pub wireframe: bool,
+    pub normals: bool,
+    pub chunk_borders: bool,
+    pub navigation_grid: bool,
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Toggle terrain wireframe", [TERRAIN_WIREFRAME_KEY]);
+    bindings.set(None, "Toggle terrain normals", [NORMALS_KEY]);
+    bindings.set(None, "Toggle chunk borders", [CHUNK_BORDERS_KEY]);
+    bindings.set(None, "Toggle navigation grid", [NAVIGATION_GRID_KEY]);
+}
+
+fn toggle_debug_views(
+    input: Res<Input<KeyCode>>,
+    render_device: Res<RenderDevice>,
+    mut config: ResMut<TerrainDebugConfig>,
+) {
+    if input.just_pressed(TERRAIN_WIREFRAME_KEY) {
+        if debug_tools::supports_wireframes(&render_device) {
+            config.wireframe = !config.wireframe;
+        } else {
+            warn!("Wireframes aren't supported by this GPU");
+        }
+    }
+    if input.just_pressed(NORMALS_KEY) {
+        config.normals = !config.normals;
+    }
+    if input.just_pressed(CHUNK_BORDERS_KEY) {
+        config.chunk_borders = !config.chunk_borders;
+    }
+    if input.just_pressed(NAVIGATION_GRID_KEY) {
+        config.navigation_grid = !config.navigation_grid;
+    }
+}
+
+/// Keeps the [`Wireframe`] of terrain chunks in sync with the config, including chunks spawned
+/// after it was toggled.
+fn update_wireframes(
+    mut commands: Commands,
+    config: Res<TerrainDebugConfig>,
+    q_chunks: Query<(Entity, Has<Wireframe>), With<TerrainChunk>>,
+) {
+    for (entity, has_wireframe) in &q_chunks {
+        if config.wireframe && !has_wireframe {
+            commands.entity(entity).insert(Wireframe);
+        } else if !config.wireframe && has_wireframe {
+            commands.entity(entity).remove::<Wireframe>();
+        }
+    }
+}
+
+/// Cells of the heightmap closer than [`VIEW_DISTANCE`] to the camera, on the `XZ` plane.
+fn nearby_cells(heightmap: &Heightmap, camera: Vec3) -> impl Iterator<Item = (u16, u16)> + '_ {
+    let clamp = |value: f32, size: u16| value.clamp(0.0, size.saturating_sub(1) as f32) as u16;
+    let x_range = clamp(camera.x - VIEW_DISTANCE, heightmap.width)
+        ..=clamp(camera.x + VIEW_DISTANCE, heightmap.width);
+    let z_range = clamp(camera.z - VIEW_DISTANCE, heightmap.depth)
+        ..=clamp(camera.z + VIEW_DISTANCE, heightmap.depth);
+
+    x_range
+        .flat_map(move |x| z_range.clone().map(move |z| (x, z)))
+        .filter(move |&(x, z)| Vec2::new(x as f32, z as f32).distance(camera.xz()) <= VIEW_DISTANCE)
+}
+
+fn surface(heightmap: &Heightmap, x: u16, z: u16) -> Vec3 {
+    Vec3::new(x as f32, heightmap.get(x, z) * HEIGHT_SCALE, z as f32)
+}
+
+fn draw_normals(
+    mut gizmos: Gizmos,
+    heightmap: Res<Heightmap>,
+    q_camera: Query<&GlobalTransform, ActiveCamera>,
+) {
+    let Ok(camera) = q_camera.get_single() else {
+        return;
+    };
+
+    if heightmap.buffer_size() == 0 {
+        return;
+    }
+
+    for (x, z) in nearby_cells(&heightmap, camera.translation()) {
+        let start = surface(&heightmap, x, z);
+        gizmos.line(
+            start,
+            start + heightmap.normal(x, z) * NORMAL_LENGTH,
+            Color::CYAN,
+        );
+    }
+}
+
+fn draw_chunk_borders(
+    mut gizmos: Gizmos,
+    heightmap: Res<Heightmap>,
+    q_chunks: Query<&TerrainChunk>,
+) {
+    if heightmap.buffer_size() == 0 {
+        return;
+    }
+
+    for chunk in &q_chunks {
+        let (x0, z0) = (chunk.x * CHUNK_SIZE, chunk.z * CHUNK_SIZE);
+        let x1 = (x0 + CHUNK_SIZE).min(heightmap.width - 1);
+        let z1 = (z0 + CHUNK_SIZE).min(heightmap.depth - 1);
+
+        // Borders follow the terrain, so they aren't hidden by hills
+        let border = (x0..x1)
+            .map(|x| (x, z0))
+            .chain((z0..z1).map(|z| (x1, z)))
+            .chain((x0 + 1..=x1).rev().map(|x| (x, z1)))
+            .chain((z0..=z1).rev().map(|z| (x0, z)))
+            .map(|(x, z)| surface(&heightmap, x, z) + Vec3::Y * SURFACE_OFFSET);
+        gizmos.linestrip(border, CHUNK_BORDER_COLOR);
+    }
+}
+
+fn draw_navigation_grid(
+    mut gizmos: Gizmos,
+    heightmap: Res<Heightmap>,
+    water_map: Res<WaterMap>,
+    q_camera: Query<&GlobalTransform, ActiveCamera>,
+) {
+    let Ok(camera) = q_camera.get_single() else {
+        return;
+    };
+
+    if heightmap.buffer_size() == 0 {
+        return;
+    }
+
+    let rotation = Quat::from_rotation_arc(Vec3::Z, Vec3::Y);
+    for (x, z) in nearby_cells(&heightmap, camera.translation()) {
+        let position = surface(&heightmap, x, z) + Vec3::Y * SURFACE_OFFSET;
+        let Some(kind) = water_map.at(position) else {
+            continue;
+        };
+
+        let color = match kind {
+            WaterKind::Land => Color::GREEN,
+            WaterKind::Shore => Color::YELLOW,
+            WaterKind::Shallow => Color::CYAN,
+            WaterKind::Deep => Color::RED,
+        };
+        gizmos.rect(position, rotation, Vec2::splat(0.8), color);
+    }
+}
@@ -0,0 +1,669 @@
+use bevy::{
+    prelude::*,
+    tasks::{block_on, IoTaskPool, Task},
+    utils::{HashMap, HashSet},
+    window::PrimaryWindow,
+};
+use bevy_inspector_egui::{
+    bevy_egui::EguiContexts, prelude::ReflectInspectorOptions, quick::ResourceInspectorPlugin,
+    InspectorOptions,
+};
+use libnoise::{Generator, Source};
+
+use crate::{
+    input_context::{
+        accepts_shortcuts, toggle_context, InputBindings, InputContext, InputContextEvent,
+        InputContextStack,
+    },
+    shutdown::{Shutdown, ShutdownRequested},
+    ActiveCamera,
+};
+
+use super::{
+    biome::{Biome, BiomeMap},
+    heightmap::Heightmap,
+    heightmap_image::{export_grayscale_png, HeightmapExportError},
+    history::{EditHistory, HistoryStep, HistoryUpdate},
+    loading::MapData,
+    mesher,
+    scatter::{self, PropAssets, ScatterChunk},
+    MapSettings, RiversAndLakes, TerrainChunk, CHUNK_SIZE,
+};
+
+/// How far, in world units, the cursor can reach the terrain.
+const MAX_CURSOR_DISTANCE: f32 = 1000.0;
+
+/// Key which toggles the [`InputContext::Editor`] context.
+const TOGGLE_KEY: KeyCode = KeyCode::F2;
+
+/// Keys which select each brush.
+const BRUSH_KEYS: [(KeyCode, Brush); 6] = [
+    (KeyCode::Key1, Brush::Raise),
+    (KeyCode::Key2, Brush::Lower),
+    (KeyCode::Key3, Brush::Smooth),
+    (KeyCode::Key4, Brush::Flatten),
+    (KeyCode::Key5, Brush::Noise),
+    (KeyCode::Key6, Brush::PaintBiome),
+];
+
+/// File the edited heightmap is exported to, as a 16-bit grayscale image, when the game is closed.
+pub const EDITED_HEIGHTMAP_FILE: &str = "edited_heightmap.png";
+
+/// Name of the [`Shutdown`] handler which exports the edited heightmap.
+const SAVE_HANDLER: &str = "Terrain editor";
+
+/// Adds [`TerrainEditorConfig`] resource and internal systems to edit the terrain using brushes
+/// at the cursor position. Editor mode is the [`InputContext::Editor`] context, toggled by `F2`.
+///
+/// Edited terrain is exported to [`EDITED_HEIGHTMAP_FILE`] when the game is closed, which is kept
+/// open until it's written. It isn't loaded back, the next game generates the map from
+/// [`MapSettings`] again, so edits are only kept on the exported image.
+///
+/// Requires [`ShutdownPlugin`](crate::shutdown::ShutdownPlugin).
+pub struct TerrainEditorPlugin;
+
+impl Plugin for TerrainEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainEditorConfig>()
+            .register_type::<TerrainEditorConfig>()
+            .add_plugins(
+                ResourceInspectorPlugin::<TerrainEditorConfig>::default().run_if(is_active),
+            )
+            .init_resource::<TerrainEditor>()
+            .init_resource::<BrushCursor>()
+            .add_event::<ShutdownRequested>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(Update, toggle_editor.run_if(accepts_shortcuts))
+            .add_systems(
+                Update,
+                (select_brush, update_cursor, apply_brush, finish_stroke)
+                    .chain()
+                    .run_if(is_active),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_edited_cells,
+                    rebuild_terrain_chunks,
+                    rescatter_chunks,
+                )
+                    .chain()
+                    .after(HistoryUpdate),
+            )
+            .add_systems(
+                Update,
+                clear_editor.run_if(resource_changed::<MapSettings>()),
+            )
+            .add_systems(
+                Update,
+                (
+                    save_on_shutdown.run_if(on_event::<ShutdownRequested>()),
+                    poll_save,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum Brush {
+    #[default]
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    Noise,
+    PaintBiome,
+}
+
+#[derive(Resource, Reflect, InspectorOptions, Debug)]
+#[reflect(Resource, InspectorOptions)]
+pub struct TerrainEditorConfig {
+    pub brush: Brush,
+    // Brush radius, in world units
+    #[inspector(min = 0.5, max = 64.0)]
+    pub radius: f32,
+    // How much, in heightmap units, the brush changes the terrain per second
+    #[inspector(min = 0.0, max = 1.0)]
+    pub strength: f32,
+    // Portion of the radius, from the border, where the brush strength fades out
+    #[inspector(min = 0.0, max = 1.0)]
+    pub falloff: f32,
+    // Biome painted by the PaintBiome brush
+    pub biome: Biome,
+}
+
+impl Default for TerrainEditorConfig {
+    fn default() -> Self {
+        Self {
+            brush: default(),
+            radius: 8.0,
+            strength: 0.1,
+            falloff: 0.5,
+            biome: Biome::Grassland,
+        }
+    }
+}
+
+/// Returns `true` when [`InputContext::Editor`] consumes input.
+pub fn is_active(stack: Res<InputContextStack>) -> bool {
+    stack.current() == InputContext::Editor
+}
+
+/// Terrain position under the cursor, if any.
+#[derive(Resource, Default)]
+struct BrushCursor(Option<Vec3>);
+
+/// Original values of every cell changed since the brush was pressed.
+struct Stroke {
+    flatten_height: f32,
+    heights: HashMap<usize, f32>,
+    biomes: HashMap<usize, Biome>,
+}
+
+/// A finished stroke, which can be undone and redone.
+#[derive(Default)]
+pub struct TerrainEdit {
+    pub brush: Brush,
+    /// Index, old and new height of each changed cell.
+    heights: Vec<(usize, f32, f32)>,
+    /// Index, old and new biome of each changed cell.
+    biomes: Vec<(usize, Biome, Biome)>,
+}
+
+#[derive(Resource, Default)]
+pub struct TerrainEditor {
+    stroke: Option<Stroke>,
+    /// Cells which height changed, so their water and biome must be classified again.
+    dirty_heights: HashSet<usize>,
+    /// Chunks which mesh must be rebuilt.
+    dirty_meshes: HashSet<(u16, u16)>,
+    /// Chunks which props must be scattered again.
+    dirty_props: HashSet<(u16, u16)>,
+    /// Whether the terrain was edited since the map was generated, so it's saved on shutdown.
+    unsaved: bool,
+}
+
+/// Edited heightmap being written on the IO task pool.
+#[derive(Resource)]
+struct HeightmapSave(Task<Result<(), HeightmapExportError>>);
+
+/// Marks all chunks which has a quad using the cell `x`, `z` as dirty.
+fn mark_dirty(dirty: &mut HashSet<(u16, u16)>, x: u16, z: u16) {
+    for chunk_x in x.saturating_sub(1) / CHUNK_SIZE..=x / CHUNK_SIZE {
+        for chunk_z in z.saturating_sub(1) / CHUNK_SIZE..=z / CHUNK_SIZE {
+            dirty.insert((chunk_x, chunk_z));
+        }
+    }
+}
+
+impl TerrainEditor {
+    /// Applies either old or new values of the given edit.
+    pub fn apply(
+        &mut self,
+        edit: &TerrainEdit,
+        use_new: bool,
+        heightmap: &mut Heightmap,
+        biome_map: &mut BiomeMap,
+    ) {
+        self.unsaved = true;
+
+        for &(index, old, new) in &edit.heights {
+            heightmap[index] = if use_new { new } else { old };
+            self.dirty_heights.insert(index);
+            let [x, z] = heightmap.position(index);
+            mark_dirty(&mut self.dirty_meshes, x, z);
+            mark_dirty(&mut self.dirty_props, x, z);
+        }
+
+        for &(index, old, new) in &edit.biomes {
+            let [x, z] = heightmap.position(index);
+            biome_map.set(x, z, if use_new { new } else { old });
+            mark_dirty(&mut self.dirty_meshes, x, z);
+            mark_dirty(&mut self.dirty_props, x, z);
+        }
+    }
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    bindings.set(None, "Toggle terrain editor", [TOGGLE_KEY]);
+    for (key, brush) in BRUSH_KEYS {
+        bindings.set(
+            Some(InputContext::Editor),
+            format!("{:?} brush", brush),
+            [key],
+        );
+    }
+}
+
+fn toggle_editor(
+    input: Res<Input<KeyCode>>,
+    stack: Res<InputContextStack>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    // Editor needs the cursor, so it's released from the camera by the context stack
+    if input.just_released(TOGGLE_KEY) {
+        toggle_context(InputContext::Editor, &stack, &mut events);
+    }
+}
+
+fn select_brush(input: Res<Input<KeyCode>>, mut config: ResMut<TerrainEditorConfig>) {
+    for (key, brush) in BRUSH_KEYS {
+        if input.just_pressed(key) {
+            config.brush = brush;
+        }
+    }
+}
+
+fn update_cursor(
+    mut cursor: ResMut<BrushCursor>,
+    mut egui_contexts: EguiContexts,
+    mut gizmos: Gizmos,
+    config: Res<TerrainEditorConfig>,
+    heightmap: Res<Heightmap>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), ActiveCamera>,
+) {
+    cursor.0 = None;
+
+    // Don't paint terrain while using inspector windows
+    if egui_contexts.ctx_mut().wants_pointer_input() {
+        return;
+    }
+
+    let (Ok(window), Ok((camera, camera_transform))) =
+        (q_window.get_single(), q_camera.get_single())
+    else {
+        return;
+    };
+
+    let Some(ray) = window
+        .cursor_position()
+        .and_then(|position| camera.viewport_to_world(camera_transform, position))
+    else {
+        return;
+    };
+
+    cursor.0 = heightmap.raycast(ray, MAX_CURSOR_DISTANCE);
+
+    if let Some(position) = cursor.0 {
+        gizmos.circle(position, Vec3::Y, config.radius, Color::YELLOW);
+        gizmos.circle(
+            position,
+            Vec3::Y,
+            config.radius * (1.0 - config.falloff),
+            Color::ORANGE,
+        );
+    }
+}
+
+/// Heights of an area of the heightmap, copied before a brush pass changes them.
+struct HeightsSnapshot {
+    min: [u16; 2],
+    depth: usize,
+    heights: Vec<f32>,
+}
+
+impl HeightsSnapshot {
+    /// Copies the heights from `min` to `max`, inclusive.
+    fn new(heightmap: &Heightmap, min: [u16; 2], max: [u16; 2]) -> Self {
+        let heights = (min[0]..=max[0])
+            .flat_map(|x| (min[1]..=max[1]).map(move |z| heightmap.get(x, z)))
+            .collect();
+
+        Self {
+            min,
+            depth: (max[1] - min[1]) as usize + 1,
+            heights,
+        }
+    }
+
+    fn get(&self, x: u16, z: u16) -> f32 {
+        self.heights[(x - self.min[0]) as usize * self.depth + (z - self.min[1]) as usize]
+    }
+
+    /// Average height of the direct neighbors of `x`, `z`, which must be inside the snapshot.
+    fn neighbors_average(&self, heightmap: &Heightmap, x: u16, z: u16) -> f32 {
+        let neighbors = heightmap.neighbors(x, z).collect::<Vec<_>>();
+        neighbors
+            .iter()
+            .map(|&[nx, nz]| self.get(nx, nz))
+            .sum::<f32>()
+            / neighbors.len() as f32
+    }
+}
+
+/// Brush strength multiplier at `ratio` of the radius from the brush center.
+fn brush_weight(ratio: f32, falloff: f32) -> f32 {
+    let inner = 1.0 - falloff;
+    if ratio <= inner {
+        1.0
+    } else {
+        let t = ((ratio - inner) / falloff.max(f32::EPSILON)).clamp(0.0, 1.0);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+}
+
+fn apply_brush(
+    time: Res<Time>,
+    mouse: Res<Input<MouseButton>>,
+    cursor: Res<BrushCursor>,
+    config: Res<TerrainEditorConfig>,
+    mut editor: ResMut<TerrainEditor>,
+    mut heightmap: ResMut<Heightmap>,
+    mut biome_map: ResMut<BiomeMap>,
+) {
+    let Some(center) = cursor.0 else {
+        return;
+    };
+
+    if mouse.just_pressed(MouseButton::Left) {
+        editor.stroke = Some(Stroke {
+            flatten_height: heightmap.sample(center.x, center.z),
+            heights: default(),
+            biomes: default(),
+        });
+    }
+
+    if !mouse.pressed(MouseButton::Left) || editor.stroke.is_none() {
+        return;
+    }
+
+    let amount = config.strength * time.delta_seconds();
+    let noise = Source::simplex(time.elapsed_seconds() as u64);
+
+    let min_x = (center.x - config.radius).floor().max(0.0) as u16;
+    let max_x = ((center.x + config.radius).ceil() as u16).min(heightmap.width - 1);
+    let min_z = (center.z - config.radius).floor().max(0.0) as u16;
+    let max_z = ((center.z + config.radius).ceil() as u16).min(heightmap.depth - 1);
+
+    let editor = &mut *editor;
+    let Some(stroke) = editor.stroke.as_mut() else {
+        return;
+    };
+
+    // Smooth reads the heights from before this pass, so it doesn't depend on the order cells are
+    // written. Neighbors of the brush border are also read
+    let snapshot = (config.brush == Brush::Smooth).then(|| {
+        HeightsSnapshot::new(
+            &heightmap,
+            [min_x.saturating_sub(1), min_z.saturating_sub(1)],
+            [
+                (max_x + 1).min(heightmap.width - 1),
+                (max_z + 1).min(heightmap.depth - 1),
+            ],
+        )
+    });
+
+    for x in min_x..=max_x {
+        for z in min_z..=max_z {
+            let distance = Vec2::new(x as f32 - center.x, z as f32 - center.z).length();
+            if distance > config.radius {
+                continue;
+            }
+
+            let weight = brush_weight(distance / config.radius, config.falloff);
+            let index = heightmap.index(x, z);
+
+            if config.brush == Brush::PaintBiome {
+                let biome = biome_map.get(x, z);
+                if weight < 0.5 || biome == config.biome {
+                    continue;
+                }
+
+                stroke.biomes.entry(index).or_insert(biome);
+                // Props are only scattered again when the stroke is done
+                biome_map.bypass_change_detection().set(x, z, config.biome);
+            } else {
+                let height = heightmap[index];
+                let target = match config.brush {
+                    Brush::Raise => height + amount,
+                    Brush::Lower => height - amount,
+                    Brush::Smooth => snapshot.as_ref().map_or(height, |snapshot| {
+                        snapshot.neighbors_average(&heightmap, x, z)
+                    }),
+                    Brush::Flatten => stroke.flatten_height,
+                    Brush::Noise => {
+                        let sample = noise.sample([x as f64 * 0.1, z as f64 * 0.1]) as f32;
+                        height + sample * amount
+                    }
+                    Brush::PaintBiome => unreachable!(),
+                };
+
+                // Raise and Lower are already scaled by time, the others moves towards the target
+                let rate = match config.brush {
+                    Brush::Raise | Brush::Lower | Brush::Noise => weight,
+                    _ => (weight * amount * 10.0).min(1.0),
+                };
+
+                stroke.heights.entry(index).or_insert(height);
+                heightmap[index] = (height + (target - height) * rate).clamp(0.0, 1.0);
+            }
+
+            mark_dirty(&mut editor.dirty_meshes, x, z);
+        }
+    }
+}
+
+/// Saves the current stroke on the undo history once the brush is released.
+fn finish_stroke(
+    mouse: Res<Input<MouseButton>>,
+    config: Res<TerrainEditorConfig>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+    mut editor: ResMut<TerrainEditor>,
+    mut history: ResMut<EditHistory>,
+) {
+    if mouse.pressed(MouseButton::Left) {
+        return;
+    }
+
+    let Some(stroke) = editor.stroke.take() else {
+        return;
+    };
+
+    let edit = TerrainEdit {
+        brush: config.brush,
+        heights: stroke
+            .heights
+            .into_iter()
+            .map(|(index, old)| (index, old, heightmap[index]))
+            .collect(),
+        biomes: stroke
+            .biomes
+            .into_iter()
+            .map(|(index, old)| {
+                let [x, z] = heightmap.position(index);
+                (index, old, biome_map.get(x, z))
+            })
+            .collect(),
+    };
+
+    editor
+        .dirty_heights
+        .extend(edit.heights.iter().map(|&(index, _, _)| index));
+
+    let changed = edit.heights.iter().map(|&(index, _, _)| index);
+    for index in changed.chain(edit.biomes.iter().map(|&(index, _, _)| index)) {
+        let [x, z] = heightmap.position(index);
+        mark_dirty(&mut editor.dirty_props, x, z);
+    }
+
+    if !edit.heights.is_empty() || !edit.biomes.is_empty() {
+        editor.unsaved = true;
+        history.push(HistoryStep::Terrain(edit));
+    }
+}
+
+/// Classifies the water and biome of edited cells again, once a stroke is done or undone, so
+/// walkability and biomes follow the new terrain. Rivers are kept, but lakes which terrain was
+/// raised above their surface are drained.
+///
+/// Biomes are only classified again on cells which height or water changed, keeping painted ones.
+fn update_edited_cells(
+    mut editor: ResMut<TerrainEditor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    settings: Res<MapSettings>,
+    mut map_data: MapData,
+    q_rivers: Query<&Handle<Mesh>, With<RiversAndLakes>>,
+) {
+    if editor.dirty_heights.is_empty() {
+        return;
+    }
+
+    let editor = &mut *editor;
+    let MapData {
+        heightmap,
+        hydrology,
+        water_map,
+        biome_map,
+    } = &mut map_data;
+    let edited = editor.dirty_heights.drain().collect::<Vec<_>>();
+
+    let mut drained = false;
+    for &index in &edited {
+        drained |= hydrology.drain_lake(index, heightmap[index]);
+    }
+
+    // Water of neighbors may also change, like shores next to raised terrain
+    let mut changed = edited
+        .iter()
+        .map(|&index| heightmap.position(index))
+        .collect::<HashSet<_>>();
+    let neighbors = changed
+        .iter()
+        .flat_map(|&[x, z]| heightmap.neighbors(x, z))
+        .collect::<Vec<_>>();
+    for [x, z] in neighbors {
+        if water_map.update(heightmap, hydrology, x, z) {
+            changed.insert([x, z]);
+        }
+    }
+    for &index in &edited {
+        let [x, z] = heightmap.position(index);
+        water_map.update(heightmap, hydrology, x, z);
+    }
+
+    // Like painting, props are only scattered again on the edited chunks
+    biome_map.bypass_change_detection().update(
+        heightmap,
+        hydrology,
+        water_map,
+        settings.seed,
+        changed.iter().copied(),
+    );
+    for &[x, z] in &changed {
+        mark_dirty(&mut editor.dirty_meshes, x, z);
+        mark_dirty(&mut editor.dirty_props, x, z);
+    }
+
+    if drained {
+        for mesh in &q_rivers {
+            meshes.insert(mesh.id(), (&**hydrology).into());
+        }
+    }
+}
+
+/// Rebuilds only the meshes of the chunks changed by the editor.
+fn rebuild_terrain_chunks(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut editor: ResMut<TerrainEditor>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+    q_chunks: Query<(&TerrainChunk, &Handle<Mesh>)>,
+) {
+    if editor.dirty_meshes.is_empty() {
+        return;
+    }
+
+    for (chunk, mesh) in &q_chunks {
+        if editor.dirty_meshes.contains(&(chunk.x, chunk.z)) {
+            meshes.insert(
+                mesh.id(),
+                mesher::chunk_mesh(&heightmap, &biome_map, chunk.x, chunk.z),
+            );
+        }
+    }
+
+    editor.dirty_meshes.clear();
+}
+
+/// Scatters again the props of the chunks changed by the editor, once a stroke is done.
+fn rescatter_chunks(
+    mut commands: Commands,
+    mut editor: ResMut<TerrainEditor>,
+    assets: Res<PropAssets>,
+    settings: Res<MapSettings>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+    q_chunks: Query<(Entity, &ScatterChunk)>,
+) {
+    if editor.dirty_props.is_empty() {
+        return;
+    }
+
+    for (entity, chunk) in &q_chunks {
+        if editor.dirty_props.remove(&(chunk.x, chunk.z)) {
+            commands.entity(entity).despawn_recursive();
+            scatter::spawn_scatter_chunk(
+                &mut commands,
+                &assets,
+                &settings,
+                &heightmap,
+                &biome_map,
+                chunk.x,
+                chunk.z,
+            );
+        }
+    }
+
+    editor.dirty_props.clear();
+}
+
+/// Any ongoing edit is lost when the map is generated again.
+fn clear_editor(mut editor: ResMut<TerrainEditor>) {
+    *editor = TerrainEditor::default();
+}
+
+/// Starts exporting the edited heightmap, keeping the game open with [`Shutdown::delay`] until
+/// it's done.
+fn save_on_shutdown(
+    mut commands: Commands,
+    mut editor: ResMut<TerrainEditor>,
+    heightmap: Res<Heightmap>,
+    mut shutdown: ResMut<Shutdown>,
+) {
+    if !editor.unsaved {
+        return;
+    }
+    editor.unsaved = false;
+
+    let heightmap = heightmap.clone();
+    let task = IoTaskPool::get()
+        .spawn(async move { export_grayscale_png(&heightmap, EDITED_HEIGHTMAP_FILE) });
+
+    shutdown.delay(SAVE_HANDLER);
+    commands.insert_resource(HeightmapSave(task));
+}
+
+fn poll_save(
+    mut commands: Commands,
+    save: Option<ResMut<HeightmapSave>>,
+    mut shutdown: ResMut<Shutdown>,
+) {
+    let Some(mut save) = save else {
+        return;
+    };
+
+    if !save.0.is_finished() {
+        return;
+    }
+
+    match block_on(&mut save.0) {
+        Ok(()) => info!("Edited heightmap exported to {}", EDITED_HEIGHTMAP_FILE),
+        Err(err) => error!("Failed to export edited heightmap: {}", err),
+    }
+
+    commands.remove_resource::<HeightmapSave>();
+    shutdown.finish(SAVE_HANDLER);
+}
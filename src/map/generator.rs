@@ -1,9 +1,6 @@
 use libnoise::{Generator, Source};
 
-use super::{
-    heightmap::{Heightmap, HeightmapSettings},
-    HeightmapLayers,
-};
+use super::heightmap::{Heightmap, HeightmapSettings};
 
 pub fn generate_terrain(settings: &HeightmapSettings) -> Heightmap {
     let mut heightmap = Heightmap::new(settings.name.clone(), settings.width, settings.depth);
@@ -27,12 +24,29 @@ pub fn generate_terrain(settings: &HeightmapSettings) -> Heightmap {
     heightmap
 }
 
-pub fn combine_heightmap_layers(layers: &HeightmapLayers) -> Heightmap {
-    let mut combined_heightmap = Heightmap::new("Final", 256, 256);
+/// Combines `layers` on a heightmap as large as the smallest of them, since layers of different
+/// sizes only overlap there.
+pub fn combine_heightmap_layers<'a>(layers: impl IntoIterator<Item = &'a Heightmap>) -> Heightmap {
+    let layers = layers.into_iter().collect::<Vec<_>>();
+    let width = layers
+        .iter()
+        .map(|layer| layer.width)
+        .min()
+        .unwrap_or_default();
+    let depth = layers
+        .iter()
+        .map(|layer| layer.depth)
+        .min()
+        .unwrap_or_default();
 
-    for heightmap in layers.iter() {
-        for (index, height) in heightmap.into_iter().enumerate() {
-            combined_heightmap[index] = (combined_heightmap[index] + height) / 2.0;
+    let mut combined_heightmap = Heightmap::new("Final", width, depth);
+
+    for heightmap in layers {
+        for z in 0..depth {
+            for x in 0..width {
+                let height = (combined_heightmap.get(x, z) + heightmap.get(x, z)) / 2.0;
+                combined_heightmap.set(x, z, height);
+            }
         }
     }
 
@@ -1,8 +1,13 @@
 use bevy::prelude::*;
 use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use serde::Deserialize;
 
-#[derive(Resource, Debug, InspectorOptions, Reflect, Clone)]
+/// World units a height of `1.0` is scaled to when building terrain meshes.
+pub const HEIGHT_SCALE: f32 = 128.0;
+
+#[derive(Resource, Debug, InspectorOptions, Reflect, Clone, PartialEq, Deserialize)]
 #[reflect(Resource, Default, Debug)]
+#[serde(default)]
 pub struct HeightmapSettings {
     pub name: String,
     pub width: u16,
@@ -16,7 +21,10 @@ pub struct HeightmapSettings {
     pub persistence: f64,
     // Initial frequency
     pub frequency: f64,
+    // Disabled layers are muted, they aren't combined on the final heightmap
     pub enabled: bool,
+    // When any layer is soloed, only soloed layers are combined on the final heightmap
+    pub solo: bool,
 }
 
 impl HeightmapSettings {
@@ -27,6 +35,15 @@ impl HeightmapSettings {
             ..Default::default()
         }
     }
+
+    /// Whether this layer is combined on the final heightmap, given if `any_solo` layer exists.
+    pub fn is_active(&self, any_solo: bool) -> bool {
+        if any_solo {
+            self.solo
+        } else {
+            self.enabled
+        }
+    }
 }
 
 impl Default for HeightmapSettings {
@@ -41,6 +58,7 @@ impl Default for HeightmapSettings {
             frequency: 1.0,
             lacunarity: 2.0,
             enabled: true,
+            solo: false,
         }
     }
 }
@@ -89,7 +107,9 @@ impl Heightmap {
 
     #[inline]
     pub fn position(&self, index: usize) -> [u16; 2] {
-        [index as u16 / self.depth, index as u16 % self.depth]
+        // Indices don't fit in `u16` on maps with more than 65536 cells
+        let depth = self.depth as usize;
+        [(index / depth) as u16, (index % depth) as u16]
     }
 
     pub fn get(&self, x: u16, z: u16) -> f32 {
@@ -104,6 +124,102 @@ impl Heightmap {
     pub fn clear(&mut self) {
         self.buffer.fill(0.0);
     }
+
+    /// Bilinear interpolation of the heights around `x`, `z`, which are clamped to the heightmap.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let x = x.clamp(0.0, (self.width - 1) as f32);
+        let z = z.clamp(0.0, (self.depth - 1) as f32);
+
+        let (x0, z0) = (x.floor() as u16, z.floor() as u16);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let (tx, tz) = (x.fract(), z.fract());
+
+        let near = self.get(x0, z0) * (1.0 - tx) + self.get(x1, z0) * tx;
+        let far = self.get(x0, z1) * (1.0 - tx) + self.get(x1, z1) * tx;
+
+        near * (1.0 - tz) + far * tz
+    }
+
+    /// Steepness of the terrain at `x`, `z`, as the rise over run in world units.
+    pub fn slope(&self, x: u16, z: u16) -> f32 {
+        let height = |x, z| self.get(x, z) * HEIGHT_SCALE;
+
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+
+        let dx = (height(x1, z) - height(x0, z)) / (x1 - x0).max(1) as f32;
+        let dz = (height(x, z1) - height(x, z0)) / (z1 - z0).max(1) as f32;
+
+        Vec2::new(dx, dz).length()
+    }
+
+    /// Surface normal of the terrain at `x`, `z`, in world units.
+    pub fn normal(&self, x: u16, z: u16) -> Vec3 {
+        let height = |x, z| self.get(x, z) * HEIGHT_SCALE;
+
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+
+        let dx = (height(x1, z) - height(x0, z)) / (x1 - x0).max(1) as f32;
+        let dz = (height(x, z1) - height(x, z0)) / (z1 - z0).max(1) as f32;
+
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    /// Finds the first point, in world units, where the given `ray` hits the terrain.
+    pub fn raycast(&self, ray: Ray, max_distance: f32) -> Option<Vec3> {
+        const STEP: f32 = 0.5;
+
+        let is_below_terrain =
+            |point: Vec3| point.y <= self.sample(point.x, point.z) * HEIGHT_SCALE;
+        let is_inside = |point: Vec3| {
+            point.x >= 0.0
+                && point.z >= 0.0
+                && point.x <= (self.width - 1) as f32
+                && point.z <= (self.depth - 1) as f32
+        };
+
+        let mut distance = 0.0;
+        while distance < max_distance {
+            let point = ray.get_point(distance + STEP);
+
+            if is_inside(point) && is_below_terrain(point) {
+                // Refines the hit point between the last two steps
+                let (mut near, mut far) = (distance, distance + STEP);
+                for _ in 0..8 {
+                    let middle = (near + far) / 2.0;
+                    if is_below_terrain(ray.get_point(middle)) {
+                        far = middle;
+                    } else {
+                        near = middle;
+                    }
+                }
+                return Some(ray.get_point(far));
+            }
+
+            distance += STEP;
+        }
+
+        None
+    }
+
+    /// Returns the position of the 4 direct neighbors of `x`, `z` which are inside the heightmap.
+    pub fn neighbors(&self, x: u16, z: u16) -> impl Iterator<Item = [u16; 2]> {
+        let (width, depth) = (self.width as i32, self.depth as i32);
+        [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .into_iter()
+            .map(move |(dx, dz)| (x as i32 + dx, z as i32 + dz))
+            .filter(move |&(nx, nz)| nx >= 0 && nx < width && nz >= 0 && nz < depth)
+            .map(|(nx, nz)| [nx as u16, nz as u16])
+    }
+
+    /// Checks if `x`, `z` is above the given `level` while having at least one neighbor below it.
+    pub fn is_shore(&self, x: u16, z: u16, level: f32) -> bool {
+        self.get(x, z) >= level
+            && self
+                .neighbors(x, z)
+                .any(|[nx, nz]| self.get(nx, nz) < level)
+    }
 }
 
 impl Default for Heightmap {
@@ -153,3 +269,19 @@ impl<'a> IntoIterator for &'a Heightmap {
         self.buffer.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_is_inverse_of_index_on_large_maps() {
+        let heightmap = Heightmap::new("", 300, 300);
+
+        for x in 0..heightmap.width {
+            for z in 0..heightmap.depth {
+                assert_eq!(heightmap.position(heightmap.index(x, z)), [x, z]);
+            }
+        }
+    }
+}
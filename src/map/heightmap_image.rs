@@ -0,0 +1,273 @@
+use std::{f32::consts::FRAC_PI_2, path::Path};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        texture::{ImageSampler, ImageSamplerDescriptor},
+    },
+};
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use thiserror::Error;
+
+use super::{biome::BiomeMap, heightmap::Heightmap};
+
+/// Elevation colors, from the lowest to the highest height.
+const COLOR_RAMP: [(f32, [f32; 3]); 7] = [
+    (0.0, [0.05, 0.1, 0.35]),
+    (0.35, [0.2, 0.4, 0.7]),
+    (0.4, [0.86, 0.8, 0.6]),
+    (0.5, [0.4, 0.65, 0.3]),
+    (0.7, [0.45, 0.35, 0.25]),
+    (0.85, [0.55, 0.55, 0.55]),
+    (1.0, [1.0, 1.0, 1.0]),
+];
+
+/// Color of contour lines, drawn on top of any [`HeightmapImageKind`].
+const CONTOUR_COLOR: [u8; 4] = [20, 20, 20, 255];
+
+/// Minimum seconds between renders of the preview image, so terrain editor strokes, which change
+/// the heightmap every frame, don't render it every frame too.
+const PREVIEW_UPDATE_INTERVAL: f32 = 0.5;
+
+/// How a [`Heightmap`] is rendered to an image.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub enum HeightmapImageKind {
+    /// Height as a shade of gray.
+    Grayscale,
+    /// Relief lit by the sun. Angles are in degrees, the `azimuth` is clockwise from north (`-Z`)
+    /// and the `altitude` is above the horizon.
+    Hillshade { azimuth: f32, altitude: f32 },
+    /// Height mapped to colors, from deep water to snow.
+    ColorRamp,
+    /// Steepness of the terrain, from flat (black) to vertical (white).
+    Slope,
+    /// Surface normals, with `XYZ` encoded as `RGB`, where `Y` is the up axis.
+    Normal,
+}
+
+impl Default for HeightmapImageKind {
+    fn default() -> Self {
+        HeightmapImageKind::Hillshade {
+            azimuth: 315.0,
+            altitude: 45.0,
+        }
+    }
+}
+
+/// How the map preview is rendered and where it's exported to.
+#[derive(Resource, Reflect, InspectorOptions, Debug, Clone, PartialEq)]
+#[reflect(Resource, InspectorOptions, Default)]
+pub struct HeightmapImageSettings {
+    pub kind: HeightmapImageKind,
+    // Height difference between contour lines, in range [0, 1]. Zero disables them
+    #[inspector(min = 0.0, max = 1.0)]
+    pub contour_interval: f32,
+    // Path, relative to the working directory, the preview is exported to as PNG
+    pub export_path: String,
+}
+
+impl Default for HeightmapImageSettings {
+    fn default() -> Self {
+        Self {
+            kind: default(),
+            contour_interval: 0.0,
+            export_path: "heightmap.png".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HeightmapExportError {
+    #[error("Failed to save heightmap image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Heightmap image size doesn't match its data")]
+    InvalidSize,
+}
+
+impl HeightmapImageKind {
+    fn pixel(&self, heightmap: &Heightmap, x: u16, z: u16) -> [u8; 4] {
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u8;
+
+        let [r, g, b] = match *self {
+            HeightmapImageKind::Grayscale => [heightmap.get(x, z); 3],
+            HeightmapImageKind::Hillshade { azimuth, altitude } => {
+                let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+                let sun = Vec3::new(
+                    azimuth.sin() * altitude.cos(),
+                    altitude.sin(),
+                    -azimuth.cos() * altitude.cos(),
+                );
+                [heightmap.normal(x, z).dot(sun).max(0.0); 3]
+            }
+            HeightmapImageKind::ColorRamp => color_ramp(heightmap.get(x, z)),
+            HeightmapImageKind::Slope => [heightmap.normal(x, z).y.acos() / FRAC_PI_2; 3],
+            HeightmapImageKind::Normal => {
+                let normal = heightmap.normal(x, z) * 0.5 + 0.5;
+                [normal.x, normal.y, normal.z]
+            }
+        };
+
+        [to_u8(r), to_u8(g), to_u8(b), 255]
+    }
+}
+
+fn color_ramp(height: f32) -> [f32; 3] {
+    let upper = COLOR_RAMP
+        .iter()
+        .position(|&(stop, _)| height <= stop)
+        .unwrap_or(COLOR_RAMP.len() - 1);
+    let (high, high_color) = COLOR_RAMP[upper];
+    let (low, low_color) = COLOR_RAMP[upper.saturating_sub(1)];
+
+    let t = if high > low {
+        (height - low) / (high - low)
+    } else {
+        0.0
+    };
+
+    [0, 1, 2].map(|i| low_color[i] + (high_color[i] - low_color[i]) * t)
+}
+
+/// Checks if a contour line crosses the cell at `x`, `z`, which happens when it's on a different
+/// contour band than its next neighbor.
+fn is_contour(heightmap: &Heightmap, x: u16, z: u16, interval: f32) -> bool {
+    let band = |x, z| (heightmap.get(x, z) / interval).floor();
+
+    let x1 = (x + 1).min(heightmap.width - 1);
+    let z1 = (z + 1).min(heightmap.depth - 1);
+
+    band(x, z) != band(x1, z) || band(x, z) != band(x, z1)
+}
+
+/// Renders the given heightmap as `RGBA` pixels. Pixels are in row order, where the image `X` axis
+/// is the world `X` axis and the image `Y` axis is the world `Z` axis.
+pub fn render_heightmap(heightmap: &Heightmap, settings: &HeightmapImageSettings) -> Vec<u8> {
+    let mut data = Vec::with_capacity(heightmap.buffer_size() * 4);
+
+    for z in 0..heightmap.depth {
+        for x in 0..heightmap.width {
+            if settings.contour_interval > 0.0
+                && is_contour(heightmap, x, z, settings.contour_interval)
+            {
+                data.extend(CONTOUR_COLOR);
+            } else {
+                data.extend(settings.kind.pixel(heightmap, x, z));
+            }
+        }
+    }
+
+    data
+}
+
+/// Renders the given heightmap to an [`Image`] which can be used on materials and egui.
+pub fn heightmap_image(heightmap: &Heightmap, settings: &HeightmapImageSettings) -> Image {
+    Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: heightmap.width as u32,
+                height: heightmap.depth as u32,
+                ..default()
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        data: render_heightmap(heightmap, settings),
+        sampler: ImageSampler::Descriptor(ImageSamplerDescriptor::nearest()),
+        ..default()
+    }
+}
+
+/// Saves `RGBA` pixels, of an image with the given size, as a PNG file at `path`.
+pub fn save_png(
+    width: u16,
+    depth: u16,
+    data: Vec<u8>,
+    path: impl AsRef<Path>,
+) -> Result<(), HeightmapExportError> {
+    let image = image::RgbaImage::from_raw(width as u32, depth as u32, data)
+        .ok_or(HeightmapExportError::InvalidSize)?;
+
+    image.save(path)?;
+    Ok(())
+}
+
+/// Renders the given heightmap and saves it as a PNG file at `path`.
+pub fn export_png(
+    heightmap: &Heightmap,
+    settings: &HeightmapImageSettings,
+    path: impl AsRef<Path>,
+) -> Result<(), HeightmapExportError> {
+    save_png(
+        heightmap.width,
+        heightmap.depth,
+        render_heightmap(heightmap, settings),
+        path,
+    )
+}
+
+/// Saves the heights as a 16-bit grayscale PNG file at `path`, which keeps enough precision to be
+/// used as a heightmap by other tools.
+pub fn export_grayscale_png(
+    heightmap: &Heightmap,
+    path: impl AsRef<Path>,
+) -> Result<(), HeightmapExportError> {
+    let data = heightmap
+        .into_iter()
+        .map(|height| (height.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+        .collect::<Vec<_>>();
+    let image = image::ImageBuffer::<image::Luma<u16>, _>::from_raw(
+        heightmap.width as u32,
+        heightmap.depth as u32,
+        data,
+    )
+    .ok_or(HeightmapExportError::InvalidSize)?;
+
+    image.save(path)?;
+    Ok(())
+}
+
+/// Renders the color of each biome as `RGBA` pixels, in the same order as [`render_heightmap`].
+pub fn render_biome_map(biome_map: &BiomeMap) -> Vec<u8> {
+    (0..biome_map.depth())
+        .flat_map(|z| (0..biome_map.width()).map(move |x| (x, z)))
+        .flat_map(|(x, z)| {
+            let [r, g, b, a] = biome_map.get(x, z).color().as_rgba_f32();
+            [r, g, b, a].map(|c| (c * 255.0) as u8)
+        })
+        .collect()
+}
+
+/// Renders the preview image of the current heightmap again, when either it or the
+/// [`HeightmapImageSettings`] changes, at most once every [`PREVIEW_UPDATE_INTERVAL`].
+pub fn update_preview_image(
+    time: Res<Time<Real>>,
+    mut images: ResMut<Assets<Image>>,
+    heightmap: Res<Heightmap>,
+    settings: Res<HeightmapImageSettings>,
+    mut dirty: Local<bool>,
+    mut last_update: Local<f32>,
+) {
+    if heightmap.is_changed() || settings.is_changed() {
+        *dirty = true;
+    }
+
+    let now = time.elapsed_seconds();
+    if !*dirty || now - *last_update < PREVIEW_UPDATE_INTERVAL {
+        return;
+    }
+
+    *dirty = false;
+    *last_update = now;
+
+    if let Some(image) = images.get_mut(&heightmap.image) {
+        *image = heightmap_image(&heightmap, &settings);
+    }
+}
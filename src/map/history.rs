@@ -0,0 +1,353 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{
+    bevy_egui::EguiContexts,
+    egui::{self, Color32},
+};
+use leafwing_input_manager::user_input::UserInput;
+
+use crate::input_context::{accepts_shortcuts, InputBindings};
+
+use super::{
+    biome::BiomeMap,
+    editor::{TerrainEdit, TerrainEditor},
+    heightmap::Heightmap,
+    MapGenerated, MapGeneration, MapSettings,
+};
+
+/// Settings changes closer than this, in seconds, are merged on a single step, so dragging a value
+/// on the inspector doesn't flood the history.
+const MERGE_WINDOW: f32 = 1.0;
+
+/// Adds [`EditHistory`] resource, which records every change made to [`MapSettings`] and to the
+/// terrain, and internal systems to undo and redo them with `Ctrl+Z` and `Ctrl+Y`. Terrain changes
+/// are dropped once the map is generated again.
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>()
+            .add_event::<HistoryCommand>()
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                Update,
+                (
+                    clear_terrain_steps.run_if(on_event::<MapGenerated>()),
+                    record_settings_change.run_if(resource_changed::<MapSettings>()),
+                    read_history_input.run_if(accepts_shortcuts),
+                    history_panel,
+                    apply_history_commands,
+                )
+                    .chain()
+                    .in_set(HistoryUpdate)
+                    .after(MapGeneration),
+            );
+    }
+}
+
+/// [`SystemSet`] used by internal systems.
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct HistoryUpdate;
+
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryCommand {
+    Undo,
+    Redo,
+}
+
+pub enum HistoryStep {
+    Settings {
+        before: Box<MapSettings>,
+        after: Box<MapSettings>,
+    },
+    Terrain(TerrainEdit),
+}
+
+impl HistoryStep {
+    fn label(&self) -> String {
+        match self {
+            HistoryStep::Settings { before, after } => {
+                let changes = [
+                    (before.seed != after.seed, "seed"),
+                    (before.layers != after.layers, "layers"),
+                    (before.sea_level != after.sea_level, "sea level"),
+                    (
+                        before.deep_water_depth != after.deep_water_depth,
+                        "deep water",
+                    ),
+                    (before.hydrology != after.hydrology, "hydrology"),
+                    (before.scatter != after.scatter, "scatter"),
+                    (before.bake != after.bake, "baking"),
+                ]
+                .into_iter()
+                .filter_map(|(changed, name)| changed.then_some(name))
+                .collect::<Vec<_>>();
+
+                format!("Map settings: {}", changes.join(", "))
+            }
+            HistoryStep::Terrain(edit) => format!("Terrain: {:?}", edit.brush),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct EditHistory {
+    /// How many steps are kept, older ones are dropped.
+    pub max_steps: usize,
+    undo: VecDeque<HistoryStep>,
+    redo: Vec<HistoryStep>,
+    /// Last known [`MapSettings`], used to know what changed.
+    settings: Option<MapSettings>,
+    /// When the last settings change happened, in seconds since startup.
+    last_settings_change: f32,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            max_steps: 100,
+            undo: default(),
+            redo: default(),
+            settings: None,
+            last_settings_change: 0.0,
+        }
+    }
+}
+
+impl EditHistory {
+    /// Records a new step, discarding anything which could be redone.
+    pub fn push(&mut self, step: HistoryStep) {
+        self.redo.clear();
+        self.undo.push_back(step);
+
+        while self.undo.len() > self.max_steps {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Records [`MapSettings`] changing to `settings`, at `now` seconds since startup. Changes
+    /// closer than [`MERGE_WINDOW`] to the last one are merged on its step.
+    fn record_settings(&mut self, settings: &MapSettings, now: f32) {
+        let Some(before) = self.settings.replace(settings.clone()) else {
+            return;
+        };
+
+        // Changed by undo or redo
+        if before == *settings {
+            return;
+        }
+
+        let merge = now - self.last_settings_change < MERGE_WINDOW && self.redo.is_empty();
+        self.last_settings_change = now;
+
+        if let (true, Some(HistoryStep::Settings { after, .. })) = (merge, self.undo.back_mut()) {
+            **after = settings.clone();
+        } else {
+            self.push(HistoryStep::Settings {
+                before: Box::new(before),
+                after: Box::new(settings.clone()),
+            });
+        }
+    }
+
+    /// Drops every terrain step, which doesn't apply to a map generated again.
+    fn clear_terrain_steps(&mut self) {
+        let is_settings = |step: &HistoryStep| matches!(step, HistoryStep::Settings { .. });
+        self.undo.retain(is_settings);
+        self.redo.retain(is_settings);
+    }
+}
+
+fn clear_terrain_steps(mut history: ResMut<EditHistory>) {
+    history.clear_terrain_steps();
+}
+
+fn record_settings_change(
+    time: Res<Time>,
+    settings: Res<MapSettings>,
+    mut history: ResMut<EditHistory>,
+) {
+    history.record_settings(&settings, time.elapsed_seconds());
+}
+
+fn register_bindings(mut bindings: ResMut<InputBindings>) {
+    for (action, key) in [("Undo", KeyCode::Z), ("Redo", KeyCode::Y)] {
+        bindings.set(
+            None,
+            action,
+            [KeyCode::ControlLeft, KeyCode::ControlRight].map(|ctrl| UserInput::chord([ctrl, key])),
+        );
+    }
+}
+
+fn read_history_input(input: Res<Input<KeyCode>>, mut commands: EventWriter<HistoryCommand>) {
+    if !input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Z) {
+        commands.send(HistoryCommand::Undo);
+    } else if input.just_pressed(KeyCode::Y) {
+        commands.send(HistoryCommand::Redo);
+    }
+}
+
+fn history_panel(
+    mut egui_contexts: EguiContexts,
+    history: Res<EditHistory>,
+    mut commands: EventWriter<HistoryCommand>,
+) {
+    egui::Window::new("History").show(egui_contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!history.undo.is_empty(), egui::Button::new("Undo"))
+                .clicked()
+            {
+                commands.send(HistoryCommand::Undo);
+            }
+            if ui
+                .add_enabled(!history.redo.is_empty(), egui::Button::new("Redo"))
+                .clicked()
+            {
+                commands.send(HistoryCommand::Redo);
+            }
+        });
+
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for step in &history.undo {
+                ui.label(step.label());
+            }
+            // Steps which can be redone are shown after the current one, faded
+            for step in history.redo.iter().rev() {
+                ui.colored_label(Color32::DARK_GRAY, step.label());
+            }
+        });
+    });
+}
+
+fn apply_history_commands(
+    mut commands: EventReader<HistoryCommand>,
+    mut history: ResMut<EditHistory>,
+    mut settings: ResMut<MapSettings>,
+    mut editor: ResMut<TerrainEditor>,
+    mut heightmap: ResMut<Heightmap>,
+    mut biome_map: ResMut<BiomeMap>,
+) {
+    for &command in commands.read() {
+        let history = &mut *history;
+        let (from, undo) = match command {
+            HistoryCommand::Undo => (history.undo.pop_back(), true),
+            HistoryCommand::Redo => (history.redo.pop(), false),
+        };
+
+        let Some(step) = from else {
+            continue;
+        };
+
+        match &step {
+            HistoryStep::Settings { before, after } => {
+                let target = if undo { before } else { after };
+                history.settings = Some((**target).clone());
+                *settings = (**target).clone();
+            }
+            HistoryStep::Terrain(edit) => {
+                // Biome edits shouldn't scatter the whole map again, only the edited chunks
+                editor.apply(
+                    edit,
+                    !undo,
+                    &mut heightmap,
+                    biome_map.bypass_change_detection(),
+                );
+            }
+        }
+
+        if undo {
+            history.redo.push(step);
+        } else {
+            history.undo.push_back(step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(seed: u64) -> MapSettings {
+        MapSettings { seed, ..default() }
+    }
+
+    fn seeds(steps: impl IntoIterator<Item = impl std::borrow::Borrow<HistoryStep>>) -> Vec<u64> {
+        steps
+            .into_iter()
+            .map(|step| match step.borrow() {
+                HistoryStep::Settings { after, .. } => after.seed,
+                HistoryStep::Terrain(_) => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drops_oldest_steps_over_limit() {
+        let mut history = EditHistory {
+            max_steps: 3,
+            ..default()
+        };
+
+        history.record_settings(&settings(0), 0.0);
+        for seed in 1..=5 {
+            history.record_settings(&settings(seed), seed as f32 * 10.0);
+        }
+
+        assert_eq!(seeds(&history.undo), [3, 4, 5]);
+    }
+
+    #[test]
+    fn merges_changes_inside_window() {
+        let mut history = EditHistory::default();
+
+        history.record_settings(&settings(0), 0.0);
+        history.record_settings(&settings(1), 10.0);
+        history.record_settings(&settings(2), 10.5);
+        history.record_settings(&settings(3), 11.0);
+        // Window starts again on each change, so this one is far enough from the last one
+        history.record_settings(&settings(4), 12.5);
+
+        assert_eq!(seeds(&history.undo), [3, 4]);
+        let Some(HistoryStep::Settings { before, .. }) = history.undo.front() else {
+            panic!("First step should be a settings change");
+        };
+        assert_eq!(before.seed, 0);
+    }
+
+    #[test]
+    fn ignores_unchanged_settings() {
+        let mut history = EditHistory::default();
+
+        history.record_settings(&settings(0), 0.0);
+        history.record_settings(&settings(0), 10.0);
+
+        assert!(history.undo.is_empty());
+    }
+
+    #[test]
+    fn clears_only_terrain_steps() {
+        let mut history = EditHistory::default();
+
+        history.record_settings(&settings(0), 0.0);
+        history.record_settings(&settings(1), 10.0);
+        history.push(HistoryStep::Terrain(TerrainEdit::default()));
+        history.record_settings(&settings(2), 20.0);
+        history
+            .redo
+            .push(HistoryStep::Terrain(TerrainEdit::default()));
+
+        history.clear_terrain_steps();
+
+        assert_eq!(seeds(&history.undo), [1, 2]);
+        assert!(history.redo.is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use serde::Deserialize;
+
+use super::heightmap::{Heightmap, HEIGHT_SCALE};
+
+/// Raise applied to each filled cell, so filled depressions still drain towards their outlet.
+const FILL_EPSILON: f32 = 1e-5;
+
+/// Minimum depth a filled depression must have to be considered a lake.
+const LAKE_MIN_DEPTH: f32 = 2e-3;
+
+#[derive(Debug, InspectorOptions, Reflect, Clone, PartialEq, Deserialize)]
+#[reflect(Default, InspectorOptions)]
+#[serde(default)]
+pub struct HydrologySettings {
+    pub enabled: bool,
+    // How many cells must drain into a cell before it becomes a river
+    #[inspector(min = 1.0)]
+    pub river_threshold: f32,
+    // How deep, in heightmap units, rivers carve the terrain when they are born
+    #[inspector(min = 0.0, max = 1.0)]
+    pub river_depth: f32,
+    // Width, in world units, of rivers when they are born
+    #[inspector(min = 0.0)]
+    pub river_width: f32,
+}
+
+impl Default for HydrologySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            river_threshold: 200.0,
+            river_depth: 0.005,
+            river_width: 1.0,
+        }
+    }
+}
+
+/// A single point of a [`River`] polyline.
+#[derive(Debug, Clone, Copy)]
+pub struct RiverPoint {
+    /// Water surface position, in world units.
+    pub position: Vec3,
+    /// River width, in world units, at this point.
+    pub width: f32,
+}
+
+/// A river polyline, going from its source down to the sea, a lake or another river.
+#[derive(Debug, Default, Clone)]
+pub struct River {
+    pub points: Vec<RiverPoint>,
+}
+
+/// Result of the hydrology pass over a [`Heightmap`].
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Hydrology {
+    depth: u16,
+    /// How many cells drain through each cell, including itself.
+    flow_accumulation: Vec<f32>,
+    /// Lake surface height of each cell, in heightmap units, if the cell is a lake.
+    lakes: Vec<Option<f32>>,
+    pub rivers: Vec<River>,
+}
+
+impl Hydrology {
+    pub fn flow_accumulation(&self, index: usize) -> f32 {
+        self.flow_accumulation[index]
+    }
+
+    pub fn lake_level(&self, index: usize) -> Option<f32> {
+        self.lakes[index]
+    }
+
+    /// Drains the lake cell at `index` when the terrain, at `height`, rises to its surface, like
+    /// after being edited. Returns `true` when it's drained.
+    pub fn drain_lake(&mut self, index: usize, height: f32) -> bool {
+        if self.lakes[index].is_some_and(|level| height >= level) {
+            self.lakes[index] = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Iterates over the position and surface height of all lake cells.
+    pub fn lakes(&self) -> impl Iterator<Item = ([u16; 2], f32)> + '_ {
+        let depth = self.depth as usize;
+        self.lakes
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, level)| {
+                level.map(|level| ([(index / depth) as u16, (index % depth) as u16], level))
+            })
+    }
+}
+
+/// Cell waiting to be processed by the priority flood, ordered by lowest height first.
+struct FloodCell {
+    height: f32,
+    index: usize,
+}
+
+impl PartialEq for FloodCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodCell {}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, since BinaryHeap is a max-heap
+        other.height.total_cmp(&self.height)
+    }
+}
+
+/// Returns the index of all 8 neighbors of the cell at `index` which are inside the heightmap.
+fn neighbors8(heightmap: &Heightmap, index: usize) -> impl Iterator<Item = usize> {
+    let [x, z] = heightmap.position(index);
+    let (width, depth) = (heightmap.width as i32, heightmap.depth as i32);
+    let (x, z) = (x as i32, z as i32);
+
+    (-1..=1)
+        .flat_map(|dx| (-1..=1).map(move |dz| (dx, dz)))
+        .filter(|&offset| offset != (0, 0))
+        .map(move |(dx, dz)| (x + dx, z + dz))
+        .filter(move |&(nx, nz)| nx >= 0 && nx < width && nz >= 0 && nz < depth)
+        .map(move |(nx, nz)| nx as usize * depth as usize + nz as usize)
+}
+
+/// Fills all depressions using a priority flood seeded by map borders and cells below
+/// `sea_level`, returning the filled heights.
+fn fill_depressions(heightmap: &Heightmap, sea_level: f32) -> Vec<f32> {
+    let size = heightmap.buffer_size();
+    let mut filled = vec![f32::NAN; size];
+    let mut queue = BinaryHeap::new();
+
+    for index in 0..size {
+        let [x, z] = heightmap.position(index);
+        let border = x == 0 || z == 0 || x == heightmap.width - 1 || z == heightmap.depth - 1;
+
+        if border || heightmap[index] < sea_level {
+            filled[index] = heightmap[index];
+            queue.push(FloodCell {
+                height: heightmap[index],
+                index,
+            });
+        }
+    }
+
+    while let Some(FloodCell { height, index }) = queue.pop() {
+        for neighbor in neighbors8(heightmap, index) {
+            if !filled[neighbor].is_nan() {
+                continue;
+            }
+
+            let neighbor_height = heightmap[neighbor].max(height + FILL_EPSILON);
+            filled[neighbor] = neighbor_height;
+            queue.push(FloodCell {
+                height: neighbor_height,
+                index: neighbor,
+            });
+        }
+    }
+
+    filled
+}
+
+/// Runs the hydrology pass over `heightmap`, carving river channels on it.
+pub fn generate_hydrology(
+    heightmap: &mut Heightmap,
+    sea_level: f32,
+    settings: &HydrologySettings,
+) -> Hydrology {
+    let size = heightmap.buffer_size();
+    let filled = fill_depressions(heightmap, sea_level);
+
+    // Steepest descent (D8) over the filled surface
+    let flow_direction = (0..size)
+        .map(|index| {
+            if heightmap[index] < sea_level {
+                return None;
+            }
+
+            let [x, z] = heightmap.position(index);
+            neighbors8(heightmap, index)
+                .map(|neighbor| {
+                    let [nx, nz] = heightmap.position(neighbor);
+                    let distance = Vec2::new(nx as f32 - x as f32, nz as f32 - z as f32).length();
+                    (neighbor, (filled[index] - filled[neighbor]) / distance)
+                })
+                .filter(|&(_, slope)| slope > 0.0)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(neighbor, _)| neighbor)
+        })
+        .collect::<Vec<_>>();
+
+    // Highest cells first, so each cell is complete before draining into the next one
+    let mut order = (0..size).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| filled[b].total_cmp(&filled[a]));
+
+    let mut flow_accumulation = vec![1.0; size];
+    for &index in &order {
+        if let Some(target) = flow_direction[index] {
+            flow_accumulation[target] += flow_accumulation[index];
+        }
+    }
+
+    let lakes = (0..size)
+        .map(|index| (filled[index] - heightmap[index] > LAKE_MIN_DEPTH).then_some(filled[index]))
+        .collect::<Vec<_>>();
+
+    let is_river = |index: usize| {
+        settings.enabled
+            && lakes[index].is_none()
+            && heightmap[index] >= sea_level
+            && flow_accumulation[index] >= settings.river_threshold
+    };
+
+    let rivers = (0..size)
+        .filter(|&index| is_river(index))
+        .collect::<Vec<_>>();
+
+    // Rivers grows wider and deeper the more water flows through it
+    let strength = |index: usize| 1.0 + (flow_accumulation[index] / settings.river_threshold).ln();
+    let carve_depth = |index: usize| settings.river_depth * strength(index);
+
+    let surface = |index: usize| {
+        if let Some(level) = lakes[index] {
+            level
+        } else if heightmap[index] < sea_level {
+            sea_level
+        } else {
+            // Water surface sits halfway between the river bed and its banks
+            heightmap[index] - carve_depth(index) / 2.0
+        }
+    };
+
+    // Sources are river cells which no other river cell drains into
+    let mut has_upstream = vec![false; size];
+    for &index in &rivers {
+        if let Some(target) = flow_direction[index] {
+            has_upstream[target] = true;
+        }
+    }
+
+    let mut network = vec![];
+    let mut visited = vec![false; size];
+    for &source in rivers.iter().filter(|&&index| !has_upstream[index]) {
+        let mut river = River::default();
+        let mut current = Some(source);
+
+        while let Some(index) = current {
+            let [x, z] = heightmap.position(index);
+            river.points.push(RiverPoint {
+                position: Vec3::new(x as f32, surface(index) * HEIGHT_SCALE, z as f32),
+                width: settings.river_width * strength(index).sqrt(),
+            });
+
+            if visited[index] || !is_river(index) {
+                // Joined another river, reached the sea or a lake
+                break;
+            }
+
+            visited[index] = true;
+            current = flow_direction[index];
+        }
+
+        if river.points.len() > 1 {
+            network.push(river);
+        }
+    }
+
+    let carved = rivers
+        .iter()
+        .map(|&index| (index, carve_depth(index)))
+        .collect::<Vec<_>>();
+    for (index, depth) in carved {
+        heightmap[index] -= depth;
+    }
+
+    Hydrology {
+        depth: heightmap.depth,
+        flow_accumulation,
+        lakes,
+        rivers: network,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heightmap(width: u16, depth: u16, height: impl Fn(u16, u16) -> f32) -> Heightmap {
+        let mut heightmap = Heightmap::new("", width, depth);
+        for x in 0..width {
+            for z in 0..depth {
+                heightmap.set(x, z, height(x, z));
+            }
+        }
+        heightmap
+    }
+
+    #[test]
+    fn pit_fills_to_its_spill_height() {
+        // Basin surrounded by a rim at 0.8, which spills through a gap at 0.6
+        let mut heightmap = heightmap(5, 5, |x, z| {
+            let border = x == 0 || z == 0 || x == 4 || z == 4;
+            match (x, z) {
+                (0, 2) => 0.6,
+                _ if border => 0.8,
+                _ => 0.3,
+            }
+        });
+
+        let hydrology = generate_hydrology(&mut heightmap, 0.0, &default());
+
+        for x in 1..4 {
+            for z in 1..4 {
+                let level = hydrology.lake_level(heightmap.index(x, z));
+                assert!(
+                    level.is_some_and(|level| (0.6..0.61).contains(&level)),
+                    "{x}, {z} is at {level:?}"
+                );
+            }
+        }
+        assert_eq!(hydrology.lake_level(heightmap.index(0, 2)), None);
+    }
+
+    #[test]
+    fn flow_accumulates_downhill() {
+        let mut heightmap = heightmap(8, 3, |x, _| 0.9 - x as f32 * 0.1);
+
+        let hydrology = generate_hydrology(&mut heightmap, 0.0, &default());
+
+        // Each cell drains into the next one downhill, on the same row
+        for x in 0..8 {
+            let accumulation = hydrology.flow_accumulation(heightmap.index(x, 1));
+            assert_eq!(accumulation, x as f32 + 1.0);
+        }
+        assert_eq!(hydrology.lakes().count(), 0);
+    }
+
+    #[test]
+    fn rivers_are_carved_where_flow_reaches_threshold() {
+        let mut heightmap = heightmap(8, 3, |x, _| 0.9 - x as f32 * 0.1);
+        let original = heightmap.clone();
+        let settings = HydrologySettings {
+            river_threshold: 4.0,
+            ..default()
+        };
+
+        let hydrology = generate_hydrology(&mut heightmap, 0.0, &settings);
+
+        for x in 0..8 {
+            let index = heightmap.index(x, 1);
+            let carved = heightmap[index] < original[index];
+            assert_eq!(carved, x >= 3, "{x}");
+        }
+        assert!(!hydrology.rivers.is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+
+use crate::game_state::{GameState, LoadingProgress};
+
+use super::{
+    bake::TerrainMaps, generate_map, generator, hydrology::Hydrology, scatter::ScatterChunk,
+    world_objects::WorldObjectsLayer, BiomeMap, GeneratedMap, Heightmap, HeightmapLayers,
+    MapGenerated, MapSettings, WaterMap,
+};
+
+/// Map being generated in the background, while the game is loading.
+#[derive(Resource)]
+pub(super) struct MapLoading {
+    task: Task<(Vec<Heightmap>, Option<GeneratedMap>)>,
+    /// Generation steps done so far: one per layer and one for the rest of the pipeline.
+    done: Arc<AtomicUsize>,
+}
+
+/// Map data resources, replaced once the map is generated and updated by the terrain editor.
+#[derive(SystemParam)]
+pub(super) struct MapData<'w> {
+    pub heightmap: ResMut<'w, Heightmap>,
+    pub hydrology: ResMut<'w, Hydrology>,
+    pub water_map: ResMut<'w, WaterMap>,
+    pub biome_map: ResMut<'w, BiomeMap>,
+}
+
+/// Starts generating the map from [`MapSettings`] on the async compute pool, so the loading
+/// screen keeps updating.
+pub(super) fn start_map_loading(
+    mut commands: Commands,
+    settings: Res<MapSettings>,
+    mut progress: ResMut<LoadingProgress>,
+) {
+    let settings = settings.clone();
+    let done = Arc::new(AtomicUsize::new(0));
+
+    progress.stage = "Generating map".to_string();
+    progress.total = settings.layers.len() + 1;
+
+    let task_done = done.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let layers = settings
+            .layers
+            .iter()
+            .map(|layer| {
+                let heightmap = generator::generate_terrain(layer);
+                task_done.fetch_add(1, Ordering::Relaxed);
+                heightmap
+            })
+            .collect::<Vec<_>>();
+
+        let generated = generate_map(&settings, &layers);
+        task_done.fetch_add(1, Ordering::Relaxed);
+
+        (layers, generated)
+    });
+
+    commands.insert_resource(MapLoading { task, done });
+}
+
+/// Reports the generation progress and, once it's done, stores the map data and sends
+/// [`MapGenerated`], so it's spawned before the game starts. An empty map can't be played, so it
+/// goes back to the main menu instead.
+pub(super) fn poll_map_loading(
+    mut commands: Commands,
+    loading: Option<ResMut<MapLoading>>,
+    mut progress: ResMut<LoadingProgress>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut layers: ResMut<HeightmapLayers>,
+    mut map_data: MapData,
+    mut map_generated: EventWriter<MapGenerated>,
+) {
+    let Some(mut loading) = loading else {
+        return;
+    };
+
+    if !loading.task.is_finished() {
+        progress.done = loading.done.load(Ordering::Relaxed);
+        return;
+    }
+
+    let (generated_layers, generated) = block_on(&mut loading.task);
+    commands.remove_resource::<MapLoading>();
+
+    layers.0 = generated_layers;
+    match generated {
+        Some(generated) => {
+            progress.done = progress.total;
+            *map_data.heightmap = generated.heightmap;
+            *map_data.hydrology = generated.hydrology;
+            *map_data.water_map = generated.water_map;
+            *map_data.biome_map = generated.biome_map;
+            map_generated.send(MapGenerated);
+        }
+        None => {
+            error!("Map settings have no active layer, going back to the main menu");
+            next_state.set(GameState::MainMenu);
+        }
+    }
+}
+
+/// Despawns props and world objects and clears the map data, so the next game starts clean. The
+/// terrain and water are despawned by [`despawn_map`](super::despawn_map).
+pub(super) fn clear_map(
+    mut commands: Commands,
+    q_props: Query<Entity, With<ScatterChunk>>,
+    q_world_objects: Query<Entity, With<WorldObjectsLayer>>,
+    mut map_data: MapData,
+) {
+    for entity in q_props.iter().chain(&q_world_objects) {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    commands.remove_resource::<TerrainMaps>();
+    *map_data.heightmap = default();
+    *map_data.hydrology = default();
+    *map_data.water_map = default();
+    *map_data.biome_map = default();
+}
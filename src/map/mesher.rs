@@ -1,34 +1,48 @@
+use std::ops::Range;
+
 use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
 
-use super::heightmap::Heightmap;
+use super::{
+    biome::BiomeMap,
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    hydrology::Hydrology,
+    CHUNK_SIZE,
+};
 
-impl From<Heightmap> for Mesh {
-    fn from(heightmap: Heightmap) -> Self {
-        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+/// Builds the terrain mesh of the chunk at `chunk_x`, `chunk_z`, colored by the biome of each cell.
+pub fn chunk_mesh(heightmap: &Heightmap, biome_map: &BiomeMap, chunk_x: u16, chunk_z: u16) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
 
-        let vertices = calc_vertices(&heightmap);
-        let normals = calc_normals(&vertices);
-        let indices = calc_indices(vertices.len());
+    let x_range = chunk_x * CHUNK_SIZE..((chunk_x + 1) * CHUNK_SIZE).min(heightmap.width - 1);
+    let z_range = chunk_z * CHUNK_SIZE..((chunk_z + 1) * CHUNK_SIZE).min(heightmap.depth - 1);
 
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-        mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
+    let vertices = calc_vertices(heightmap, x_range.clone(), z_range.clone());
+    let normals = calc_normals(&vertices);
+    let tangents = calc_tangents(&normals);
+    let uvs = calc_uvs(heightmap, &vertices);
+    let colors = calc_colors(biome_map, x_range, z_range);
+    let indices = calc_indices(vertices.len());
 
-        mesh
-    }
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
+
+    mesh
 }
 
 #[inline]
 fn calc_vertice_at(x: u16, z: u16, heightmap: &Heightmap) -> [f32; 3] {
     let height = heightmap.get(x, z);
-    let scale = 128.0;
-    [x as f32, height * scale, z as f32]
+    [x as f32, height * HEIGHT_SCALE, z as f32]
 }
 
-fn calc_vertices(heightmap: &Heightmap) -> Vec<[f32; 3]> {
+fn calc_vertices(heightmap: &Heightmap, x_range: Range<u16>, z_range: Range<u16>) -> Vec<[f32; 3]> {
     let mut vertices = vec![];
-    for x in 0..heightmap.width - 1 {
-        for z in 0..heightmap.depth - 1 {
+    for x in x_range {
+        for z in z_range.clone() {
             let v0 = calc_vertice_at(x, z, heightmap);
             let v1 = calc_vertice_at(x, z + 1, heightmap);
             let v2 = calc_vertice_at(x + 1, z + 1, heightmap);
@@ -42,6 +56,13 @@ fn calc_vertices(heightmap: &Heightmap) -> Vec<[f32; 3]> {
     vertices
 }
 
+fn calc_colors(biome_map: &BiomeMap, x_range: Range<u16>, z_range: Range<u16>) -> Vec<[f32; 4]> {
+    x_range
+        .flat_map(|x| z_range.clone().map(move |z| (x, z)))
+        .flat_map(|(x, z)| [biome_map.get(x, z).color().as_linear_rgba_f32(); 4])
+        .collect()
+}
+
 fn calc_normals(vertices: &[[f32; 3]]) -> Vec<[f32; 3]> {
     vertices
         .chunks(4)
@@ -57,6 +78,36 @@ fn calc_normals(vertices: &[[f32; 3]]) -> Vec<[f32; 3]> {
         .collect()
 }
 
+/// Tangent of the terrain surface with the given `normal`. It follows the `X` axis, like the
+/// texture `U` coordinate, while the bitangent follows the `Z` axis, like the `V` coordinate.
+pub fn tangent(normal: Vec3) -> Vec3 {
+    (Vec3::X - normal * normal.x).normalize()
+}
+
+fn calc_tangents(normals: &[[f32; 3]]) -> Vec<[f32; 4]> {
+    normals
+        .iter()
+        .map(|&normal| {
+            let [x, y, z] = tangent(normal.into()).to_array();
+            // Bitangent is `w * cross(normal, tangent)`, which must point to `+Z`
+            [x, y, z, -1.0]
+        })
+        .collect()
+}
+
+/// Texture coordinates covering the whole heightmap, each vertex at the center of its texel.
+fn calc_uvs(heightmap: &Heightmap, vertices: &[[f32; 3]]) -> Vec<[f32; 2]> {
+    vertices
+        .iter()
+        .map(|&[x, _, z]| {
+            [
+                (x + 0.5) / heightmap.width as f32,
+                (z + 0.5) / heightmap.depth as f32,
+            ]
+        })
+        .collect()
+}
+
 fn calc_indices(vertices_count: usize) -> Vec<u32> {
     (0..vertices_count as u32)
         .step_by(4)
@@ -74,3 +125,49 @@ fn calc_indices(vertices_count: usize) -> Vec<u32> {
         })
         .collect()
 }
+
+impl From<&Hydrology> for Mesh {
+    fn from(hydrology: &Hydrology) -> Self {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        for river in &hydrology.rivers {
+            let first = vertices.len() as u32;
+
+            for (i, point) in river.points.iter().enumerate() {
+                let previous = river.points[i.saturating_sub(1)].position;
+                let next = river.points[(i + 1).min(river.points.len() - 1)].position;
+                let side = (next - previous).cross(Vec3::Y).normalize_or_zero() * point.width / 2.0;
+
+                vertices.push((point.position - side).into());
+                vertices.push((point.position + side).into());
+            }
+
+            for segment in 0..river.points.len() as u32 - 1 {
+                let index = first + segment * 2;
+                indices.extend([index, index + 2, index + 1, index + 1, index + 2, index + 3]);
+            }
+        }
+
+        for ([x, z], level) in hydrology.lakes() {
+            let index = vertices.len() as u32;
+            let (x, y, z) = (x as f32, level * HEIGHT_SCALE, z as f32);
+
+            vertices.push([x - 0.5, y, z - 0.5]);
+            vertices.push([x - 0.5, y, z + 0.5]);
+            vertices.push([x + 0.5, y, z + 0.5]);
+            vertices.push([x + 0.5, y, z - 0.5]);
+            indices.extend([index, index + 1, index + 2, index, index + 2, index + 3]);
+        }
+
+        let normals = vec![[0.0, 1.0, 0.0]; vertices.len()];
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indices)));
+
+        mesh
+    }
+}
@@ -0,0 +1,227 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_inspector_egui::{
+    bevy_egui::EguiContexts,
+    egui::{self, Color32, Pos2, Stroke},
+};
+
+use crate::{game_state::GameState, input_context::ActiveController, player::MoveTarget};
+
+use super::{
+    biome::BiomeMap,
+    heightmap::Heightmap,
+    world_objects::{Portal, SpawnPoint, TerrainAnchor},
+    TerrainChunk,
+};
+
+/// Size, in pixels, of the minimap widget.
+const MINIMAP_SIZE: f32 = 256.0;
+
+/// Only entities closer than this to the player are shown on the minimap.
+const NEARBY_DISTANCE: f32 = 96.0;
+
+/// Minimum seconds between renders of the minimap, so terrain editor strokes, which change the
+/// terrain every frame, don't render it every frame too.
+const UPDATE_INTERVAL: f32 = 0.5;
+
+/// Light direction used to shade the relief, coming from the north-west like on most maps.
+const LIGHT_DIRECTION: Vec3 = Vec3::new(-1.0, 1.0, -1.0);
+
+/// Adds a minimap window, rendered from the current [`Heightmap`] and [`BiomeMap`], which shows
+/// the active [`Player`](crate::player::Player) and nearby entities and sets the player
+/// [`MoveTarget`] to the clicked point. It's only shown while [`GameState::InGame`].
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Minimap>().add_systems(
+            Update,
+            (update_minimap_image, minimap_window)
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+struct Minimap {
+    image: Handle<Image>,
+    /// Whether the terrain changed since the image was rendered.
+    dirty: bool,
+    /// When the image was last rendered, in seconds since startup.
+    last_update: f32,
+}
+
+/// Shaded relief of the terrain, colored by biome. The image `X` axis is the world `X` axis and
+/// its `Y` axis is the world `Z` axis.
+fn minimap_image(heightmap: &Heightmap, biome_map: &BiomeMap) -> Image {
+    let light = LIGHT_DIRECTION.normalize();
+    let has_biomes = biome_map.width() == heightmap.width && biome_map.depth() == heightmap.depth;
+
+    let mut data = Vec::with_capacity(heightmap.buffer_size() * 4);
+    for z in 0..heightmap.depth {
+        for x in 0..heightmap.width {
+            let shade = 0.4 + 0.6 * heightmap.normal(x, z).dot(light).max(0.0);
+
+            let color = if has_biomes {
+                biome_map.get(x, z).color()
+            } else {
+                Color::GRAY
+            };
+            let [r, g, b, _] = color.as_rgba_f32();
+            data.extend([r, g, b].map(|c| (c * shade * 255.0) as u8));
+            data.push(255);
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: heightmap.width as u32,
+            height: heightmap.depth as u32,
+            ..default()
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Renders the minimap again when the terrain is generated or edited, at most once every
+/// [`UPDATE_INTERVAL`].
+fn update_minimap_image(
+    time: Res<Time<Real>>,
+    mut images: ResMut<Assets<Image>>,
+    mut minimap: ResMut<Minimap>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    q_chunks: Query<&Handle<Mesh>, With<TerrainChunk>>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+) {
+    // Terrain edits may only change biomes, without triggering change detection
+    let chunk_edited = mesh_events.read().any(|event| match event {
+        AssetEvent::Modified { id } => q_chunks.iter().any(|handle| handle.id() == *id),
+        _ => false,
+    });
+
+    if heightmap.is_changed() || biome_map.is_changed() || chunk_edited {
+        minimap.dirty = true;
+    }
+
+    let now = time.elapsed_seconds();
+    let first = minimap.image == Handle::default();
+    if !minimap.dirty || (!first && now - minimap.last_update < UPDATE_INTERVAL) {
+        return;
+    }
+
+    if heightmap.buffer_size() == 0 {
+        return;
+    }
+
+    minimap.dirty = false;
+    minimap.last_update = now;
+
+    let image = minimap_image(&heightmap, &biome_map);
+    if minimap.image == Handle::default() {
+        minimap.image = images.add(image);
+    } else {
+        images.insert(minimap.image.id(), image);
+    }
+}
+
+fn minimap_window(
+    mut egui_contexts: EguiContexts,
+    minimap: Res<Minimap>,
+    heightmap: Res<Heightmap>,
+    // Only players have a move target
+    mut q_player: Query<(&GlobalTransform, &mut MoveTarget), With<ActiveController>>,
+    q_nearby: Query<(
+        &TerrainAnchor,
+        &GlobalTransform,
+        Has<Portal>,
+        Has<SpawnPoint>,
+    )>,
+) {
+    if minimap.image == Handle::default() {
+        return;
+    }
+
+    let texture = egui_contexts.add_image(minimap.image.clone());
+    let size = Vec2::new(
+        heightmap.width.saturating_sub(1).max(1) as f32,
+        heightmap.depth.saturating_sub(1).max(1) as f32,
+    );
+
+    egui::Window::new("Minimap")
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            let response = ui.add(
+                egui::Image::new((texture, egui::Vec2::splat(MINIMAP_SIZE)))
+                    .sense(egui::Sense::click()),
+            );
+            let rect = response.rect;
+
+            let to_minimap = |position: Vec3| {
+                let uv = Vec2::new(position.x, position.z) / size;
+                rect.lerp_inside(egui::vec2(uv.x, uv.y))
+            };
+            let to_world = |pos: Pos2| {
+                let uv = (pos - rect.min) / rect.size();
+                Vec2::new(uv.x, uv.y) * size
+            };
+
+            let painter = ui.painter_at(rect);
+
+            let Ok((player, mut move_target)) = q_player.get_single_mut() else {
+                return;
+            };
+            let player_position = player.translation();
+
+            // Only world objects are shown, props would clutter the minimap
+            for (_, transform, portal, spawn_point) in &q_nearby {
+                let position = transform.translation();
+                if position.xz().distance(player_position.xz()) > NEARBY_DISTANCE {
+                    continue;
+                }
+
+                let color = if portal {
+                    Color32::from_rgb(200, 60, 220)
+                } else if spawn_point {
+                    Color32::GREEN
+                } else {
+                    Color32::WHITE
+                };
+                painter.circle_filled(to_minimap(position), 2.5, color);
+            }
+
+            if let Some(target) = move_target.0 {
+                painter.circle_stroke(
+                    to_minimap(Vec3::new(target.x, 0.0, target.y)),
+                    4.0,
+                    Stroke::new(1.5, Color32::YELLOW),
+                );
+            }
+
+            // Player is an arrow pointing to where it's facing
+            let center = to_minimap(player_position);
+            let forward = player.forward().xz().normalize_or_zero();
+            let forward = egui::vec2(forward.x, forward.y);
+            let side = egui::vec2(-forward.y, forward.x);
+            painter.add(egui::Shape::convex_polygon(
+                vec![
+                    center + forward * 8.0,
+                    center - forward * 4.0 + side * 5.0,
+                    center - forward * 4.0 - side * 5.0,
+                ],
+                Color32::RED,
+                Stroke::new(1.0, Color32::BLACK),
+            ));
+
+            if response.clicked() {
+                if let Some(pos) = response.interact_pointer_pos() {
+                    move_target.0 = Some(to_world(pos));
+                }
+            }
+        });
+}
@@ -1,39 +1,149 @@
-use bevy::{
-    prelude::*,
-    render::{
-        render_resource::{
-            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
-        },
-        texture::{ImageSampler, ImageSamplerDescriptor},
-    },
-};
-use bevy_inspector_egui::{
-    prelude::ReflectInspectorOptions, quick::ResourceInspectorPlugin, InspectorOptions,
-};
+use bevy::prelude::*;
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use serde::Deserialize;
+
+use crate::game_state::{in_game, GameState, OnExitGame};
 
 use self::{
+    bake::{BakeSettings, TerrainMaps},
     generator::combine_heightmap_layers,
-    heightmap::{Heightmap, HeightmapSettings},
+    heightmap::HeightmapSettings,
+    hydrology::{Hydrology, HydrologySettings},
+    scatter::{PropAssets, ScatterChunk, ScatterSettings},
+    world_objects::{
+        TerrainAnchor, WorldObjectAssets, WorldObjects, WorldObjectsHandle, WorldObjectsLoader,
+    },
 };
 
+mod bake;
+mod biome;
+#[cfg(feature = "dev-tools")]
+mod debug_view;
+#[cfg(feature = "dev-tools")]
+mod editor;
 mod generator;
 mod heightmap;
+mod heightmap_image;
+#[cfg(feature = "dev-tools")]
+mod history;
+mod hydrology;
+mod loading;
 mod mesher;
+mod minimap;
+#[cfg(feature = "dev-tools")]
+mod panel;
+mod scatter;
+mod water;
+mod world_objects;
 
-pub struct MapPlugin;
+/// Size, in cells, of each side of a map chunk.
+pub const CHUNK_SIZE: u16 = 32;
 
-impl Plugin for MapPlugin {
+pub use biome::{Biome, BiomeMap};
+#[cfg(feature = "dev-tools")]
+pub use debug_view::{TerrainDebugConfig, TerrainDebugPlugin};
+pub use heightmap::{Heightmap, HEIGHT_SCALE};
+pub use heightmap_image::{
+    export_grayscale_png, export_png, render_biome_map, save_png, HeightmapImageKind,
+    HeightmapImageSettings,
+};
+pub use water::{WaterKind, WaterMap};
+pub use world_objects::{Portal, SpawnPoint, WorldObjectsConfig};
+
+/// Generates the map data, like the [`Heightmap`], [`WaterMap`] and [`BiomeMap`], whenever
+/// [`MapSettings`] changes. It doesn't render anything, so it also works on headless apps.
+pub struct MapLogicPlugin;
+
+impl Plugin for MapLogicPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_test_environment)
-            .init_resource::<HeightmapLayers>()
-            .add_plugins(ResourceInspectorPlugin::<HeightmapLayers>::default())
+        app.init_resource::<HeightmapLayers>()
             .init_resource::<MapSettings>()
-            .add_plugins(ResourceInspectorPlugin::<MapSettings>::default())
             .register_type::<HeightmapSettings>()
             .register_type::<Heightmap>()
+            .register_type::<HydrologySettings>()
+            .register_type::<ScatterSettings>()
+            .register_type::<BakeSettings>()
+            .init_resource::<Heightmap>()
+            .init_resource::<Hydrology>()
+            .init_resource::<WaterMap>()
+            .init_resource::<BiomeMap>()
+            .add_event::<MapGenerated>()
             .add_systems(
                 Update,
-                generate_heightmap.run_if(resource_changed::<MapSettings>()),
+                generate_map_data
+                    .run_if(resource_changed::<MapSettings>())
+                    .in_set(MapGeneration),
+            );
+    }
+}
+
+/// [`SystemSet`] where map data is generated. Systems which depends on [`MapGenerated`] should
+/// run after it.
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct MapGeneration;
+
+/// Sent when a new map is generated, but not when [`MapSettings`] has no active layer.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MapGenerated;
+
+/// Adds [`MapLogicPlugin`] and everything needed to show and edit the map.
+///
+/// The map is generated while [`GameState::Loading`], regenerated when [`MapSettings`] changes
+/// while [`GameState::InGame`] and cleared on [`OnExitGame`]. Requires
+/// [`GameStatePlugin`](crate::game_state::GameStatePlugin).
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MapLogicPlugin)
+            .configure_sets(Update, MapGeneration.run_if(in_state(GameState::InGame)))
+            .add_systems(
+                OnEnter(GameState::Loading),
+                (
+                    loading::start_map_loading,
+                    world_objects::load_world_objects,
+                ),
+            )
+            .add_systems(OnExitGame, (despawn_map, loading::clear_map))
+            .init_resource::<HeightmapImageSettings>()
+            .register_type::<HeightmapImageSettings>()
+            .register_type::<HeightmapImageKind>()
+            .add_plugins(minimap::MinimapPlugin)
+            .init_resource::<PropAssets>()
+            .init_asset::<WorldObjects>()
+            .init_asset_loader::<WorldObjectsLoader>()
+            .init_resource::<WorldObjectsConfig>()
+            .init_resource::<WorldObjectsHandle>()
+            .init_resource::<WorldObjectAssets>()
+            .register_type::<TerrainAnchor>()
+            .register_type::<SpawnPoint>()
+            .register_type::<Portal>()
+            .register_type::<ScatterChunk>()
+            .add_systems(
+                Update,
+                (
+                    loading::poll_map_loading
+                        .run_if(in_state(GameState::Loading))
+                        .before(MapGeneration),
+                    despawn_map
+                        .run_if(resource_changed::<MapSettings>())
+                        .after(MapGeneration),
+                    create_heightmap_images
+                        .run_if(
+                            resource_changed::<MapSettings>().or_else(on_event::<MapGenerated>()),
+                        )
+                        .after(MapGeneration)
+                        .after(loading::poll_map_loading),
+                    (spawn_heightmap_preview, spawn_terrain, spawn_water)
+                        .run_if(on_event::<MapGenerated>())
+                        .after(create_heightmap_images),
+                    scatter::scatter_props.run_if(resource_changed::<BiomeMap>()),
+                    world_objects::spawn_loaded_world_objects
+                        .run_if(in_state(GameState::Loading).or_else(in_game)),
+                    world_objects::place_on_terrain,
+                    bake::rebake_edited_chunks,
+                    heightmap_image::update_preview_image,
+                ),
             );
     }
 }
@@ -42,59 +152,95 @@ impl Plugin for MapPlugin {
 #[reflect(Resource, InspectorOptions, Default)]
 struct HeightmapLayers(pub Vec<Heightmap>);
 
-#[derive(Resource, Default, Reflect, InspectorOptions, Deref, DerefMut)]
+/// Tools used to author maps: the map generation panel, the terrain editor and their undo history.
+#[cfg(feature = "dev-tools")]
+pub struct MapEditorPlugin;
+
+#[cfg(feature = "dev-tools")]
+impl Plugin for MapEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            editor::TerrainEditorPlugin,
+            history::HistoryPlugin,
+            panel::MapPanelPlugin,
+        ));
+    }
+}
+
+/// Everything needed to generate a map. Changing it generates the map again.
+#[derive(Resource, Reflect, InspectorOptions, Debug, Clone, PartialEq, Deserialize)]
 #[reflect(Resource, InspectorOptions, Default)]
-struct MapSettings(pub Vec<HeightmapSettings>);
+#[serde(default)]
+pub struct MapSettings {
+    // Seed used by everything which isn't a heightmap layer, like biomes and props
+    pub seed: u64,
+    pub layers: Vec<HeightmapSettings>,
+    // Height, in range [0, 1], below which terrain is covered by water
+    #[inspector(min = 0.0, max = 1.0)]
+    pub sea_level: f32,
+    // How far below sea level water becomes too deep to walk on
+    #[inspector(min = 0.0, max = 1.0)]
+    pub deep_water_depth: f32,
+    pub hydrology: HydrologySettings,
+    pub scatter: ScatterSettings,
+    pub bake: BakeSettings,
+}
 
-#[derive(Component)]
-struct HeightmapMarker;
+impl MapSettings {
+    /// Layers combined on the final heightmap, with their index: the soloed ones when any layer is
+    /// soloed, or else the enabled ones.
+    pub fn active_layers(&self) -> impl Iterator<Item = (usize, &HeightmapSettings)> {
+        let any_solo = self.layers.iter().any(|layer| layer.solo);
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(move |(_, layer)| layer.is_active(any_solo))
+    }
 
-fn setup_test_environment(
-    mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-) {
-    const MAP_SIZE: u32 = 500;
+    /// Size, in cells, of the map generated from these settings, which is the area covered by
+    /// every active layer.
+    pub fn size(&self) -> (u16, u16) {
+        let default = HeightmapSettings::default();
+        let width = self.active_layers().map(|(_, layer)| layer.width).min();
+        let depth = self.active_layers().map(|(_, layer)| layer.depth).min();
 
-    let obstacle_model = PbrBundle {
-        mesh: meshes.add(shape::Cube::default().into()),
-        material: materials.add(StandardMaterial { ..default() }),
-        ..Default::default()
-    };
+        (
+            width.unwrap_or(default.width),
+            depth.unwrap_or(default.depth),
+        )
+    }
+}
 
-    const HALF_SIZE: f32 = MAP_SIZE as f32 / 2.0;
-    const OBSTACLE_COUNT: u32 = MAP_SIZE / 10;
+impl Default for MapSettings {
+    fn default() -> Self {
+        Self {
+            seed: 42,
+            // A single layer, so the game can be played without a map settings file
+            layers: vec![HeightmapSettings {
+                name: "Terrain".to_string(),
+                ..default()
+            }],
+            sea_level: 0.4,
+            deep_water_depth: 0.05,
+            hydrology: default(),
+            scatter: default(),
+            bake: default(),
+        }
+    }
+}
 
-    commands
-        .spawn((SpatialBundle::default(), Name::new("Obstacles")))
-        .with_children(|parent| {
-            for x in 0..=OBSTACLE_COUNT {
-                for z in 0..=OBSTACLE_COUNT {
-                    parent.spawn((
-                        PbrBundle {
-                            transform: Transform::from_xyz(
-                                (x * 10) as f32 - HALF_SIZE,
-                                0.5,
-                                (z * 10) as f32 - HALF_SIZE,
-                            )
-                            .with_scale(Vec3::splat(0.5)),
-                            ..obstacle_model.clone()
-                        },
-                        Name::new(format!("{}, {}", x, z)),
-                    ));
-                }
-            }
-        });
+#[derive(Component)]
+struct HeightmapMarker;
 
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
-            ..Default::default()
-        },
-        transform: Transform::from_xyz(0.0, 100.0, 0.0)
-            .with_rotation(Quat::from_rotation_x(-std::f32::consts::PI / 4.0)),
-        ..Default::default()
-    });
+/// Tags the mesh entity of rivers and lakes.
+#[derive(Component)]
+struct RiversAndLakes;
+
+/// Tags the terrain mesh entity of the chunk at `x`, `z`.
+#[derive(Component)]
+struct TerrainChunk {
+    x: u16,
+    z: u16,
 }
 
 impl From<&mut Heightmap> for Image {
@@ -105,70 +251,111 @@ impl From<&mut Heightmap> for Image {
 
 impl From<&Heightmap> for Image {
     fn from(heightmap: &Heightmap) -> Self {
-        let width = heightmap.width as u32;
-        let depth = heightmap.depth as u32;
-        let data = heightmap
-            .into_iter()
-            .flat_map(|h| {
-                // Convert from [0,1] to [0,255]
-                let c = (h * 255.0) as u8;
-                [c, c, c, 255]
-            })
-            .collect::<Vec<_>>();
-
-        Image {
-            texture_descriptor: TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width,
-                    height: depth,
-                    ..default()
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
-                usage: TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            },
-            data,
-            sampler: ImageSampler::Descriptor(ImageSamplerDescriptor::nearest()),
+        let settings = HeightmapImageSettings {
+            kind: HeightmapImageKind::Grayscale,
             ..default()
-        }
+        };
+        heightmap_image::heightmap_image(heightmap, &settings)
     }
 }
 
-fn generate_heightmap(
-    mut commands: Commands,
-    q_existing_heightmap: Query<Entity, With<HeightmapMarker>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut images: ResMut<Assets<Image>>,
+/// Everything generated from [`MapSettings`], before anything is spawned.
+pub struct GeneratedMap {
+    pub heightmap: Heightmap,
+    pub hydrology: Hydrology,
+    pub water_map: WaterMap,
+    pub biome_map: BiomeMap,
+}
+
+/// Generates the heightmap of every layer, including muted ones, so they can be previewed.
+pub fn generate_layers(settings: &MapSettings) -> Vec<Heightmap> {
+    settings
+        .layers
+        .iter()
+        .map(generator::generate_terrain)
+        .collect()
+}
+
+/// Runs the whole generation pipeline over the given `layers`, generated by [`generate_layers`].
+/// Returns `None` when no layer is active.
+pub fn generate_map(settings: &MapSettings, layers: &[Heightmap]) -> Option<GeneratedMap> {
+    let active_layers = settings
+        .active_layers()
+        .filter_map(|(index, _)| layers.get(index))
+        .collect::<Vec<_>>();
+
+    if active_layers.is_empty() {
+        return None;
+    }
+
+    let mut heightmap = combine_heightmap_layers(active_layers);
+    let hydrology =
+        hydrology::generate_hydrology(&mut heightmap, settings.sea_level, &settings.hydrology);
+    let water_map = WaterMap::new(
+        &heightmap,
+        &hydrology,
+        settings.sea_level,
+        settings.deep_water_depth,
+    );
+    let biome_map = BiomeMap::new(&heightmap, &hydrology, &water_map, settings.seed);
+
+    Some(GeneratedMap {
+        heightmap,
+        hydrology,
+        water_map,
+        biome_map,
+    })
+}
+
+/// Generates the map data whenever [`MapSettings`] changes.
+fn generate_map_data(
     mut layers: ResMut<HeightmapLayers>,
+    mut heightmap: ResMut<Heightmap>,
+    mut hydrology: ResMut<Hydrology>,
+    mut water_map: ResMut<WaterMap>,
+    mut biome_map: ResMut<BiomeMap>,
+    mut map_generated: EventWriter<MapGenerated>,
     settings: Res<MapSettings>,
 ) {
-    // Clear existing heightmaps entities
-    for entity in &q_existing_heightmap {
-        commands.entity(entity).despawn_recursive();
-    }
-    layers.clear();
+    layers.0 = generate_layers(&settings);
 
-    for settings in settings.iter() {
-        if !settings.enabled {
-            continue;
-        }
+    let Some(generated) = generate_map(&settings, &layers) else {
+        // Clears everything which depends on the terrain, like props
+        *biome_map = BiomeMap::default();
+        return;
+    };
 
-        let mut heightmap = generator::generate_terrain(settings);
-        heightmap.image = images.add((&heightmap).into());
-        layers.push(heightmap);
-    }
+    *heightmap = generated.heightmap;
+    *hydrology = generated.hydrology;
+    *water_map = generated.water_map;
+    *biome_map = generated.biome_map;
+    map_generated.send(MapGenerated);
+}
 
-    if layers.is_empty() {
-        return;
+fn despawn_map(mut commands: Commands, q_existing_heightmap: Query<Entity, With<HeightmapMarker>>) {
+    for entity in &q_existing_heightmap {
+        commands.entity(entity).despawn_recursive();
     }
+}
 
-    let heightmap = combine_heightmap_layers(layers.as_ref());
+/// Creates the images used to preview each layer and the final heightmap.
+fn create_heightmap_images(
+    mut images: ResMut<Assets<Image>>,
+    mut layers: ResMut<HeightmapLayers>,
+    mut heightmap: ResMut<Heightmap>,
+) {
+    for layer in layers.iter_mut() {
+        layer.image = images.add((&*layer).into());
+    }
+    heightmap.image = images.add((&*heightmap).into());
+}
 
+fn spawn_heightmap_preview(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    heightmap: Res<Heightmap>,
+) {
     commands.spawn((
         PbrBundle {
             mesh: meshes.add(
@@ -185,14 +372,79 @@ fn generate_heightmap(
         Name::new("Heightmap texture"),
         HeightmapMarker,
     ));
+}
+
+fn spawn_terrain(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+    settings: Res<MapSettings>,
+) {
+    // Terrain is split in chunks, so editing it only rebuilds the affected chunks
+    let mut terrain_material = StandardMaterial {
+        perceptual_roughness: 0.9,
+        ..default()
+    };
+    if settings.bake.enabled {
+        // Baked maps add smooth normals and ambient occlusion to the flat shaded terrain quads
+        let terrain_maps = TerrainMaps {
+            settings: settings.bake.clone(),
+            normal_map: images.add(bake::bake_normal_map(&heightmap)),
+            occlusion: images.add(bake::bake_ambient_occlusion(&heightmap, &settings.bake)),
+        };
+        terrain_material.normal_map_texture = Some(terrain_maps.normal_map.clone());
+        terrain_material.occlusion_texture = Some(terrain_maps.occlusion.clone());
+        commands.insert_resource(terrain_maps);
+    } else {
+        commands.remove_resource::<TerrainMaps>();
+    }
+    let terrain_material = materials.add(terrain_material);
+    commands
+        .spawn((
+            SpatialBundle::default(),
+            Name::new("Terrain"),
+            HeightmapMarker,
+        ))
+        .with_children(|parent| {
+            for x in 0..(heightmap.width - 1).div_ceil(CHUNK_SIZE) {
+                for z in 0..(heightmap.depth - 1).div_ceil(CHUNK_SIZE) {
+                    parent.spawn((
+                        PbrBundle {
+                            mesh: meshes.add(mesher::chunk_mesh(&heightmap, &biome_map, x, z)),
+                            material: terrain_material.clone(),
+                            ..default()
+                        },
+                        Name::new(format!("Chunk {}, {}", x, z)),
+                        TerrainChunk { x, z },
+                    ));
+                }
+            }
+        });
+}
+
+fn spawn_water(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    hydrology: Res<Hydrology>,
+    water_map: Res<WaterMap>,
+) {
+    commands.spawn((
+        water::water_plane_bundle(&water_map, &mut meshes, &mut materials),
+        HeightmapMarker,
+    ));
 
     commands.spawn((
         PbrBundle {
-            mesh: meshes.add(heightmap.clone().into()),
-            material: materials.add(Color::LIME_GREEN.into()),
+            mesh: meshes.add((&*hydrology).into()),
+            material: materials.add(water::water_material()),
             ..default()
         },
-        Name::new("Terrain"),
+        Name::new("Rivers and lakes"),
         HeightmapMarker,
+        RiversAndLakes,
     ));
 }
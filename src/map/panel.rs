@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{
+    bevy_egui::EguiContexts,
+    egui::{self, Rect},
+    reflect_inspector,
+};
+
+use crate::rng::Rng;
+
+use super::{
+    heightmap::{Heightmap, HeightmapSettings},
+    heightmap_image::{self, HeightmapImageSettings},
+    HeightmapLayers, MapSettings,
+};
+
+/// Size, in pixels, of the preview image of each layer.
+const THUMBNAIL_SIZE: f32 = 64.0;
+
+/// Size, in pixels, of the preview image of the combined heightmap.
+const PREVIEW_SIZE: f32 = 256.0;
+
+/// Adds the "Map generation" panel, used to edit [`MapSettings`] and preview its layers, and the
+/// "Map preview" panel, which shows and exports the combined heightmap.
+pub struct MapPanelPlugin;
+
+impl Plugin for MapPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (map_panel, preview_panel));
+    }
+}
+
+#[derive(Default)]
+struct PanelState {
+    /// Index of the layer being dragged to a new position.
+    dragging: Option<usize>,
+    /// Images registered on egui, so they can be freed once a new heightmap is generated.
+    textures: Vec<Handle<Image>>,
+}
+
+fn random_seed(time: &Time, seed: u64) -> u64 {
+    Rng::new(seed ^ time.elapsed().as_nanos() as u64).next_u64()
+}
+
+fn map_panel(
+    mut egui_contexts: EguiContexts,
+    mut state: Local<PanelState>,
+    type_registry: Res<AppTypeRegistry>,
+    time: Res<Time>,
+    layers: Res<HeightmapLayers>,
+    mut settings: ResMut<MapSettings>,
+) {
+    // Images are generated again every time settings changes, so old ones must be released
+    let shown = layers
+        .iter()
+        .map(|heightmap| heightmap.image.clone())
+        .collect::<Vec<_>>();
+    for handle in std::mem::take(&mut state.textures) {
+        if !shown.contains(&handle) {
+            egui_contexts.remove_image(&handle);
+        }
+    }
+    let thumbnails = layers
+        .iter()
+        .map(|layer| egui_contexts.add_image(layer.image.clone()))
+        .collect::<Vec<_>>();
+    state.textures = shown;
+
+    // Edits are made on a copy, so settings are only changed, and the map generated again, when
+    // something was actually edited
+    let mut edited = settings.clone();
+    let type_registry = type_registry.read();
+
+    egui::Window::new("Map generation").show(egui_contexts.ctx_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(&mut edited.seed));
+                if ui.button("Randomize").clicked() {
+                    edited.seed = random_seed(&time, edited.seed);
+                }
+            });
+            ui.add(egui::Slider::new(&mut edited.sea_level, 0.0..=1.0).text("Sea level"));
+            ui.add(
+                egui::Slider::new(&mut edited.deep_water_depth, 0.0..=1.0).text("Deep water depth"),
+            );
+            ui.collapsing("Hydrology", |ui| {
+                reflect_inspector::ui_for_value(&mut edited.hydrology, ui, &type_registry);
+            });
+            ui.collapsing("Scatter", |ui| {
+                reflect_inspector::ui_for_value(&mut edited.scatter, ui, &type_registry);
+            });
+            ui.collapsing("Baking", |ui| {
+                reflect_inspector::ui_for_value(&mut edited.bake, ui, &type_registry);
+            });
+
+            ui.separator();
+            ui.heading("Layers");
+
+            let mut rows = Vec::<Rect>::new();
+            let mut removed = None;
+            for (index, layer) in edited.layers.iter_mut().enumerate() {
+                let row = ui.horizontal(|ui| {
+                    let handle = ui.add(egui::Label::new("☰").sense(egui::Sense::drag()));
+                    if handle.drag_started() {
+                        state.dragging = Some(index);
+                    }
+
+                    match thumbnails.get(index) {
+                        Some(&texture) => {
+                            ui.image((texture, egui::Vec2::splat(THUMBNAIL_SIZE)));
+                        }
+                        None => {
+                            ui.add_sized([THUMBNAIL_SIZE; 2], egui::Spinner::new());
+                        }
+                    }
+
+                    ui.vertical(|ui| {
+                        ui.text_edit_singleline(&mut layer.name);
+                        ui.horizontal(|ui| {
+                            ui.toggle_value(&mut layer.solo, "Solo");
+                            let mut muted = !layer.enabled;
+                            if ui.toggle_value(&mut muted, "Mute").changed() {
+                                layer.enabled = !muted;
+                            }
+                            if ui.button("Randomize").clicked() {
+                                layer.seed = random_seed(&time, layer.seed);
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    });
+                });
+                rows.push(row.response.rect);
+
+                ui.push_id(index, |ui| {
+                    ui.collapsing("Settings", |ui| {
+                        reflect_inspector::ui_for_value(layer, ui, &type_registry);
+                    });
+                });
+            }
+
+            if let Some(index) = removed {
+                edited.layers.remove(index);
+                state.dragging = None;
+            }
+
+            // Moves the dragged layer to the row below the pointer, once it's released
+            if let Some(from) = state.dragging.filter(|&from| from < rows.len()) {
+                let pointer = ui.input(|input| input.pointer.interact_pos());
+                let to = pointer
+                    .and_then(|pointer| rows.iter().position(|row| pointer.y < row.bottom()))
+                    .unwrap_or(rows.len() - 1);
+
+                ui.painter()
+                    .rect_stroke(rows[to], 2.0, ui.visuals().selection.stroke);
+
+                if ui.input(|input| input.pointer.any_released()) {
+                    let layer = edited.layers.remove(from);
+                    edited.layers.insert(to, layer);
+                    state.dragging = None;
+                }
+            } else {
+                state.dragging = None;
+            }
+
+            if ui.button("Add layer").clicked() {
+                edited.layers.push(HeightmapSettings {
+                    name: format!("Layer {}", edited.layers.len()),
+                    seed: random_seed(&time, edited.seed),
+                    ..default()
+                });
+            }
+        });
+    });
+
+    if edited != *settings {
+        *settings = edited;
+    }
+}
+
+fn preview_panel(
+    mut egui_contexts: EguiContexts,
+    mut shown: Local<Handle<Image>>,
+    type_registry: Res<AppTypeRegistry>,
+    heightmap: Res<Heightmap>,
+    settings: Res<MapSettings>,
+    mut image_settings: ResMut<HeightmapImageSettings>,
+) {
+    if *shown != heightmap.image {
+        egui_contexts.remove_image(&shown);
+        *shown = heightmap.image.clone();
+    }
+    let preview = egui_contexts.add_image(heightmap.image.clone());
+
+    let mut edited = image_settings.clone();
+    let type_registry = type_registry.read();
+
+    egui::Window::new("Map preview").show(egui_contexts.ctx_mut(), |ui| {
+        if settings.active_layers().next().is_none() {
+            ui.label("No layer is active");
+            return;
+        }
+
+        ui.image((preview, egui::Vec2::splat(PREVIEW_SIZE)));
+        reflect_inspector::ui_for_value(&mut edited, ui, &type_registry);
+
+        if ui.button("Export PNG").clicked() {
+            match heightmap_image::export_png(&heightmap, &edited, &edited.export_path) {
+                Ok(()) => info!("Heightmap exported to {}", edited.export_path),
+                Err(err) => error!("Failed to export heightmap: {}", err),
+            }
+        }
+    });
+
+    if edited != *image_settings {
+        *image_settings = edited;
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    f32::consts::{SQRT_2, TAU},
+    ops::Range,
+};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use serde::Deserialize;
+
+use crate::rng::Rng;
+
+use super::{
+    biome::{Biome, BiomeMap},
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    MapSettings, CHUNK_SIZE,
+};
+
+/// How many candidates are tried around a point before giving up on it.
+const POISSON_ATTEMPTS: u32 = 30;
+
+#[derive(Debug, InspectorOptions, Reflect, Clone, PartialEq, Deserialize)]
+#[reflect(Default, InspectorOptions)]
+#[serde(default)]
+pub struct ScatterSettings {
+    pub enabled: bool,
+    // Multiplier of how many props are placed, 1.0 being the default density
+    #[inspector(min = 0.0, max = 4.0)]
+    pub density: f32,
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            density: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PropKind {
+    Tree,
+    Rock,
+    Grass,
+}
+
+impl PropKind {
+    /// Height from the prop mesh origin to its base.
+    fn base_offset(self) -> f32 {
+        match self {
+            PropKind::Tree => 1.15,
+            PropKind::Rock => 0.1,
+            PropKind::Grass => 0.15,
+        }
+    }
+}
+
+/// Where and how sparse a [`PropKind`] is placed.
+struct ScatterRule {
+    kind: PropKind,
+    min_distance: f32,
+    biomes: &'static [Biome],
+    max_slope: f32,
+    height: Range<f32>,
+}
+
+const SCATTER_RULES: [ScatterRule; 3] = [
+    ScatterRule {
+        kind: PropKind::Tree,
+        min_distance: 4.0,
+        biomes: &[Biome::Forest],
+        max_slope: 0.8,
+        height: 0.0..0.7,
+    },
+    ScatterRule {
+        kind: PropKind::Rock,
+        min_distance: 12.0,
+        biomes: &[
+            Biome::Beach,
+            Biome::Desert,
+            Biome::Grassland,
+            Biome::Mountain,
+        ],
+        max_slope: 3.0,
+        height: 0.0..1.0,
+    },
+    ScatterRule {
+        kind: PropKind::Grass,
+        min_distance: 3.0,
+        biomes: &[Biome::Grassland, Biome::Forest],
+        max_slope: 1.0,
+        height: 0.0..0.6,
+    },
+];
+
+/// Tags the entity parent of all props placed on the chunk at `x`, `z`.
+#[derive(Component, Reflect, Debug, Default)]
+#[reflect(Component)]
+pub struct ScatterChunk {
+    pub x: u16,
+    pub z: u16,
+}
+
+/// Meshes and materials shared by all props of the same [`PropKind`].
+#[derive(Resource)]
+pub struct PropAssets {
+    tree: (Handle<Mesh>, Handle<StandardMaterial>),
+    rock: (Handle<Mesh>, Handle<StandardMaterial>),
+    grass: (Handle<Mesh>, Handle<StandardMaterial>),
+}
+
+impl PropAssets {
+    fn get(&self, kind: PropKind) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+        match kind {
+            PropKind::Tree => self.tree.clone(),
+            PropKind::Rock => self.rock.clone(),
+            PropKind::Grass => self.grass.clone(),
+        }
+    }
+}
+
+impl FromWorld for PropAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let tree_mesh = meshes.add(
+            shape::Capsule {
+                radius: 0.4,
+                depth: 1.5,
+                ..default()
+            }
+            .into(),
+        );
+        let rock_mesh = meshes.add(
+            shape::UVSphere {
+                radius: 0.5,
+                sectors: 8,
+                stacks: 6,
+            }
+            .into(),
+        );
+        let grass_mesh = meshes.add(shape::Box::new(0.5, 0.3, 0.5).into());
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        Self {
+            tree: (tree_mesh, materials.add(Color::DARK_GREEN.into())),
+            rock: (rock_mesh, materials.add(Color::GRAY.into())),
+            grass: (grass_mesh, materials.add(Color::GREEN.into())),
+        }
+    }
+}
+
+/// Bridson's Poisson-disk sampling inside a square of `size` units, so no two points are closer
+/// than `min_distance` from each other.
+fn poisson_disk(rng: &mut Rng, size: f32, min_distance: f32) -> Vec<Vec2> {
+    let cell_size = min_distance / SQRT_2;
+    let grid_size = (size / cell_size).ceil() as usize;
+    let grid_cell = |p: Vec2| {
+        (
+            ((p.x / cell_size) as usize).min(grid_size - 1),
+            ((p.y / cell_size) as usize).min(grid_size - 1),
+        )
+    };
+
+    let mut grid = vec![None; grid_size * grid_size];
+    let mut points = vec![];
+    let mut active = vec![];
+
+    let first = Vec2::new(rng.next_f32() * size, rng.next_f32() * size);
+    let (gx, gz) = grid_cell(first);
+    grid[gx * grid_size + gz] = Some(0);
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let active_index = (rng.next_u64() % active.len() as u64) as usize;
+        let point = points[active[active_index]];
+
+        let candidate = (0..POISSON_ATTEMPTS).find_map(|_| {
+            let direction = Vec2::from_angle(rng.next_f32() * TAU);
+            let candidate = point + direction * min_distance * (1.0 + rng.next_f32());
+
+            if candidate.x < 0.0 || candidate.y < 0.0 || candidate.x >= size || candidate.y >= size
+            {
+                return None;
+            }
+
+            let (gx, gz) = grid_cell(candidate);
+            let too_close = (gx.saturating_sub(2)..(gx + 3).min(grid_size))
+                .flat_map(|x| (gz.saturating_sub(2)..(gz + 3).min(grid_size)).map(move |z| (x, z)))
+                .filter_map(|(x, z)| grid[x * grid_size + z])
+                .any(|other: usize| points[other].distance(candidate) < min_distance);
+
+            (!too_close).then_some(candidate)
+        });
+
+        if let Some(candidate) = candidate {
+            let (gx, gz) = grid_cell(candidate);
+            grid[gx * grid_size + gz] = Some(points.len());
+            active.push(points.len());
+            points.push(candidate);
+        } else {
+            active.swap_remove(active_index);
+        }
+    }
+
+    points
+}
+
+/// Places all props inside the chunk at `chunk_x`, `chunk_z`, returning their transforms.
+fn scatter_chunk(
+    heightmap: &Heightmap,
+    biome_map: &BiomeMap,
+    seed: u64,
+    density: f32,
+    chunk_x: u16,
+    chunk_z: u16,
+) -> Vec<(PropKind, Transform)> {
+    let origin = Vec2::new(chunk_x as f32, chunk_z as f32) * CHUNK_SIZE as f32;
+    let mut props = vec![];
+
+    for (rule_index, rule) in SCATTER_RULES.iter().enumerate() {
+        // Each chunk and rule has its own seed, so chunks can be scattered independently
+        let chunk_seed = seed
+            ^ ((chunk_x as u64) << 48)
+            ^ ((chunk_z as u64) << 32)
+            ^ ((rule_index as u64) << 16);
+        let mut rng = Rng::new(Rng::new(chunk_seed).next_u64());
+
+        let min_distance = rule.min_distance / density.sqrt();
+        for point in poisson_disk(&mut rng, CHUNK_SIZE as f32, min_distance) {
+            let position = origin + point;
+            let (x, z) = (position.x.round(), position.y.round());
+            if x >= biome_map.width() as f32 || z >= biome_map.depth() as f32 {
+                continue;
+            }
+
+            let (x, z) = (x as u16, z as u16);
+            let height = heightmap.sample(position.x, position.y);
+            if !rule.biomes.contains(&biome_map.get(x, z))
+                || heightmap.slope(x, z) > rule.max_slope
+                || !rule.height.contains(&height)
+            {
+                continue;
+            }
+
+            let scale = 0.75 + rng.next_f32() * 0.5;
+            let translation = Vec3::new(
+                point.x,
+                height * HEIGHT_SCALE + rule.kind.base_offset() * scale,
+                point.y,
+            );
+
+            props.push((
+                rule.kind,
+                Transform::from_translation(translation)
+                    .with_rotation(Quat::from_rotation_y(rng.next_f32() * TAU))
+                    .with_scale(Vec3::splat(scale)),
+            ));
+        }
+    }
+
+    props
+}
+
+/// Spawns a [`ScatterChunk`] entity with all props placed inside the chunk at `chunk_x`, `chunk_z`.
+pub fn spawn_scatter_chunk(
+    commands: &mut Commands,
+    assets: &PropAssets,
+    settings: &MapSettings,
+    heightmap: &Heightmap,
+    biome_map: &BiomeMap,
+    chunk_x: u16,
+    chunk_z: u16,
+) {
+    let density = settings.scatter.density;
+    let props = if settings.scatter.enabled && density > 0.0 {
+        scatter_chunk(
+            heightmap,
+            biome_map,
+            settings.seed,
+            density,
+            chunk_x,
+            chunk_z,
+        )
+    } else {
+        vec![]
+    };
+    let origin = Vec3::new(chunk_x as f32, 0.0, chunk_z as f32) * CHUNK_SIZE as f32;
+
+    commands
+        .spawn((
+            SpatialBundle::from_transform(Transform::from_translation(origin)),
+            Name::new(format!("Props {}, {}", chunk_x, chunk_z)),
+            ScatterChunk {
+                x: chunk_x,
+                z: chunk_z,
+            },
+        ))
+        .with_children(|parent| {
+            for (kind, transform) in props {
+                let (mesh, material) = assets.get(kind);
+                parent.spawn(PbrBundle {
+                    mesh,
+                    material,
+                    transform,
+                    ..default()
+                });
+            }
+        });
+}
+
+/// Scatters props over the whole map, spawning a [`ScatterChunk`] entity per chunk.
+pub fn scatter_props(
+    mut commands: Commands,
+    q_chunks: Query<Entity, With<ScatterChunk>>,
+    assets: Res<PropAssets>,
+    settings: Res<MapSettings>,
+    heightmap: Res<Heightmap>,
+    biome_map: Res<BiomeMap>,
+) {
+    for entity in &q_chunks {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let chunks_x = biome_map.width().div_ceil(CHUNK_SIZE);
+    let chunks_z = biome_map.depth().div_ceil(CHUNK_SIZE);
+
+    for chunk_x in 0..chunks_x {
+        for chunk_z in 0..chunks_z {
+            spawn_scatter_chunk(
+                &mut commands,
+                &assets,
+                &settings,
+                &heightmap,
+                &biome_map,
+                chunk_x,
+                chunk_z,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{generate_layers, generate_map};
+
+    use super::*;
+
+    #[test]
+    fn poisson_disk_points_keep_min_distance() {
+        let mut rng = Rng::new(7);
+        let points = poisson_disk(&mut rng, 32.0, 3.0);
+
+        assert!(points.len() > 10);
+        for (i, a) in points.iter().enumerate() {
+            assert!(a.cmpge(Vec2::ZERO).all() && a.cmplt(Vec2::splat(32.0)).all());
+            for b in &points[i + 1..] {
+                assert!(a.distance(*b) >= 3.0, "{a} and {b} are too close");
+            }
+        }
+    }
+
+    #[test]
+    fn props_follow_their_rules() {
+        let settings = MapSettings::default();
+        let layers = generate_layers(&settings);
+        let map = generate_map(&settings, &layers).unwrap();
+        let (heightmap, biome_map) = (&map.heightmap, &map.biome_map);
+
+        let mut count = 0;
+        for chunk_x in 0..heightmap.width.div_ceil(CHUNK_SIZE) {
+            for chunk_z in 0..heightmap.depth.div_ceil(CHUNK_SIZE) {
+                let props = scatter_chunk(heightmap, biome_map, 42, 1.0, chunk_x, chunk_z);
+                assert_eq!(
+                    props,
+                    scatter_chunk(heightmap, biome_map, 42, 1.0, chunk_x, chunk_z),
+                    "same seed must place the same props"
+                );
+
+                let origin = Vec2::new(chunk_x as f32, chunk_z as f32) * CHUNK_SIZE as f32;
+                for (i, (kind, transform)) in props.iter().enumerate() {
+                    let rule = SCATTER_RULES
+                        .iter()
+                        .find(|rule| rule.kind == *kind)
+                        .unwrap();
+                    let position = origin + transform.translation.xz();
+                    let (x, z) = (position.x.round() as u16, position.y.round() as u16);
+
+                    assert!(rule.biomes.contains(&biome_map.get(x, z)));
+                    assert!(heightmap.slope(x, z) <= rule.max_slope);
+                    assert!(rule
+                        .height
+                        .contains(&heightmap.sample(position.x, position.y)));
+
+                    for (other_kind, other) in &props[i + 1..] {
+                        if other_kind == kind {
+                            let distance =
+                                transform.translation.xz().distance(other.translation.xz());
+                            assert!(distance >= rule.min_distance);
+                        }
+                    }
+                }
+
+                count += props.len();
+            }
+        }
+
+        assert!(count > 0);
+    }
+}
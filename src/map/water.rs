@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+
+use super::{
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    hydrology::Hydrology,
+};
+
+/// Classification of a terrain cell regarding the sea level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum WaterKind {
+    /// Dry land, above the sea level.
+    #[default]
+    Land,
+    /// Dry land touching the water.
+    Shore,
+    /// Below sea level, but shallow enough to walk on.
+    Shallow,
+    /// Below sea level and too deep to walk on.
+    Deep,
+}
+
+impl WaterKind {
+    /// Characters can walk on anything but deep water.
+    pub fn is_walkable(self) -> bool {
+        self != WaterKind::Deep
+    }
+
+    /// Characters swims when they are on any water below the sea level.
+    pub fn is_swimmable(self) -> bool {
+        matches!(self, WaterKind::Shallow | WaterKind::Deep)
+    }
+}
+
+/// Holds the [`WaterKind`] of each cell of the final [`Heightmap`], so navigation and movement
+/// can query it without touching the heightmap itself.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct WaterMap {
+    width: u16,
+    depth: u16,
+    sea_level: f32,
+    deep_water_depth: f32,
+    cells: Vec<WaterKind>,
+}
+
+impl WaterMap {
+    /// Classifies every cell of `heightmap` using `sea_level` and `deep_water_depth`, both in
+    /// heightmap units (`[0, 1]`). Lakes found by [`Hydrology`] are classified using their own
+    /// surface level instead of the sea level.
+    pub fn new(
+        heightmap: &Heightmap,
+        hydrology: &Hydrology,
+        sea_level: f32,
+        deep_water_depth: f32,
+    ) -> Self {
+        let mut water_map = Self {
+            width: heightmap.width,
+            depth: heightmap.depth,
+            sea_level,
+            deep_water_depth,
+            cells: vec![WaterKind::Land; heightmap.buffer_size()],
+        };
+
+        for index in 0..heightmap.buffer_size() {
+            let [x, z] = heightmap.position(index);
+            water_map.update(heightmap, hydrology, x, z);
+        }
+
+        water_map
+    }
+
+    /// Classifies the cell at `x`, `z` again, like after the terrain is edited. Returns `true` when
+    /// its [`WaterKind`] changed.
+    pub fn update(&mut self, heightmap: &Heightmap, hydrology: &Hydrology, x: u16, z: u16) -> bool {
+        let is_lake = |x, z| hydrology.lake_level(heightmap.index(x, z)).is_some();
+
+        let index = heightmap.index(x, z);
+        let height = heightmap[index];
+        let level = hydrology.lake_level(index).unwrap_or(self.sea_level);
+
+        let kind = if height < level - self.deep_water_depth {
+            WaterKind::Deep
+        } else if height < level {
+            WaterKind::Shallow
+        } else if heightmap.is_shore(x, z, self.sea_level)
+            || heightmap.neighbors(x, z).any(|[nx, nz]| is_lake(nx, nz))
+        {
+            WaterKind::Shore
+        } else {
+            WaterKind::Land
+        };
+
+        let changed = self.cells[index] != kind;
+        self.cells[index] = kind;
+        changed
+    }
+
+    pub fn get(&self, x: u16, z: u16) -> WaterKind {
+        self.cells[x as usize * self.depth as usize + z as usize]
+    }
+
+    /// Returns the [`WaterKind`] of the cell under the given world `position`, if it's inside map.
+    pub fn at(&self, position: Vec3) -> Option<WaterKind> {
+        let (x, z) = (position.x.round(), position.z.round());
+        if x < 0.0 || z < 0.0 || x >= self.width as f32 || z >= self.depth as f32 {
+            None
+        } else {
+            Some(self.get(x as u16, z as u16))
+        }
+    }
+
+    /// Sea level in world units.
+    pub fn sea_level_height(&self) -> f32 {
+        self.sea_level * HEIGHT_SCALE
+    }
+}
+
+/// Tags the water plane entity.
+#[derive(Component)]
+pub struct Water;
+
+/// Material shared by all water surfaces, like the sea, rivers and lakes.
+pub fn water_material() -> StandardMaterial {
+    StandardMaterial {
+        base_color: Color::rgba(0.1, 0.35, 0.6, 0.7),
+        perceptual_roughness: 0.1,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    }
+}
+
+/// Water plane covering the whole terrain at sea level.
+pub fn water_plane_bundle(
+    water_map: &WaterMap,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> impl Bundle {
+    let width = water_map.width as f32 - 1.0;
+    let depth = water_map.depth as f32 - 1.0;
+
+    (
+        PbrBundle {
+            mesh: meshes.add(shape::Plane::from_size(1.0).into()),
+            material: materials.add(water_material()),
+            transform: Transform::from_xyz(width / 2.0, water_map.sea_level_height(), depth / 2.0)
+                .with_scale(Vec3::new(width, 1.0, depth)),
+            ..default()
+        },
+        Name::new("Water"),
+        Water,
+    )
+}
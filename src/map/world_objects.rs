@@ -0,0 +1,353 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{
+    heightmap::{Heightmap, HEIGHT_SCALE},
+    MapSettings,
+};
+
+/// Which world objects are spawned when the map is loaded.
+#[derive(Resource, Debug, Clone)]
+pub struct WorldObjectsConfig {
+    /// Asset path of the map data file.
+    pub path: String,
+    /// Also spawn the obstacle grid used to test movement and camera, set by
+    /// [`DebugConfig::obstacle_grid`](crate::config::DebugConfig::obstacle_grid).
+    pub debug_grid: bool,
+}
+
+impl Default for WorldObjectsConfig {
+    fn default() -> Self {
+        Self {
+            path: "maps/world.objects.ron".to_string(),
+            debug_grid: false,
+        }
+    }
+}
+
+/// World objects of a map, as described on its map data file.
+#[derive(Asset, TypePath, Deserialize, Debug, Clone, Default)]
+pub struct WorldObjects {
+    pub objects: Vec<WorldObject>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorldObject {
+    pub name: String,
+    pub kind: WorldObjectKind,
+    /// Position on the `XZ` plane. The height is taken from the terrain.
+    pub position: [f32; 2],
+    /// Height above the terrain.
+    #[serde(default)]
+    pub height_offset: f32,
+    /// Rotation around the `Y` axis, in degrees.
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum WorldObjectKind {
+    Obstacle {
+        size: [f32; 3],
+    },
+    SpawnPoint,
+    Light {
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+    Portal {
+        destination: [f32; 2],
+    },
+}
+
+impl WorldObjects {
+    /// Grid of small cubes used to test movement and camera, covering a map of `width` by `depth`
+    /// cells.
+    pub fn debug_grid(width: u16, depth: u16) -> Self {
+        const SPACING: u16 = 10;
+
+        // Centered on the map, so there is the same margin on every border
+        let cells = |size: u16| size.saturating_sub(1) / SPACING;
+        let margin = |size: u16| (size.saturating_sub(1) % SPACING) as f32 / 2.0;
+        let (margin_x, margin_z) = (margin(width), margin(depth));
+
+        let objects = (0..=cells(width))
+            .flat_map(|x| (0..=cells(depth)).map(move |z| (x, z)))
+            .map(|(x, z)| WorldObject {
+                name: format!("{}, {}", x, z),
+                kind: WorldObjectKind::Obstacle {
+                    size: [0.5, 0.5, 0.5],
+                },
+                position: [
+                    (x * SPACING) as f32 + margin_x,
+                    (z * SPACING) as f32 + margin_z,
+                ],
+                height_offset: 0.5,
+                rotation: 0.0,
+            })
+            .collect();
+
+        Self { objects }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum WorldObjectsLoaderError {
+    #[error("Failed to read world objects file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse world objects file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+#[derive(Default)]
+pub struct WorldObjectsLoader;
+
+impl AssetLoader for WorldObjectsLoader {
+    type Asset = WorldObjects;
+    type Settings = ();
+    type Error = WorldObjectsLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["objects.ron"]
+    }
+}
+
+/// Keeps the map data file loaded, so it's reloaded when changed.
+#[derive(Resource, Default)]
+pub struct WorldObjectsHandle(Handle<WorldObjects>);
+
+/// Tags the entity parent of all objects spawned from a [`WorldObjects`].
+#[derive(Component)]
+pub struct WorldObjectsLayer;
+
+/// Keeps an entity on top of the terrain, even when it's regenerated.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct TerrainAnchor {
+    pub height_offset: f32,
+}
+
+/// Where characters are spawned.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct SpawnPoint;
+
+/// Moves characters to its `destination`, on the `XZ` plane.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Portal {
+    pub destination: Vec2,
+}
+
+/// Meshes and materials shared by all world objects.
+#[derive(Resource)]
+pub struct WorldObjectAssets {
+    obstacle: (Handle<Mesh>, Handle<StandardMaterial>),
+    portal: (Handle<Mesh>, Handle<StandardMaterial>),
+}
+
+impl FromWorld for WorldObjectAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let obstacle_mesh = meshes.add(shape::Cube::default().into());
+        let portal_mesh = meshes.add(
+            shape::Torus {
+                radius: 1.0,
+                ring_radius: 0.15,
+                ..default()
+            }
+            .into(),
+        );
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        Self {
+            obstacle: (obstacle_mesh, materials.add(StandardMaterial::default())),
+            portal: (
+                portal_mesh,
+                materials.add(StandardMaterial {
+                    base_color: Color::PURPLE,
+                    emissive: Color::PURPLE,
+                    ..default()
+                }),
+            ),
+        }
+    }
+}
+
+/// Loads the map data file. When it's already loaded, like on a second game, there is no load
+/// event, so its objects are spawned right away.
+pub fn load_world_objects(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    world_objects: Res<Assets<WorldObjects>>,
+    assets: Res<WorldObjectAssets>,
+    config: Res<WorldObjectsConfig>,
+    settings: Res<MapSettings>,
+) {
+    let handle = asset_server.load(&config.path);
+    if let Some(objects) = world_objects.get(&handle) {
+        spawn_world_objects(&mut commands, &assets, objects, &config.path);
+    }
+    commands.insert_resource(WorldObjectsHandle(handle));
+
+    if config.debug_grid {
+        let (width, depth) = settings.size();
+        spawn_world_objects(
+            &mut commands,
+            &assets,
+            &WorldObjects::debug_grid(width, depth),
+            "Debug grid",
+        );
+    }
+}
+
+/// Spawns world objects when the map data file is loaded, and respawns them when it's changed.
+pub fn spawn_loaded_world_objects(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<WorldObjects>>,
+    q_layers: Query<(Entity, &Name), With<WorldObjectsLayer>>,
+    world_objects: Res<Assets<WorldObjects>>,
+    handle: Res<WorldObjectsHandle>,
+    assets: Res<WorldObjectAssets>,
+    config: Res<WorldObjectsConfig>,
+) {
+    for event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+
+        if *id != handle.0.id() {
+            continue;
+        }
+
+        let Some(objects) = world_objects.get(*id) else {
+            continue;
+        };
+
+        for (entity, name) in &q_layers {
+            if name.as_str() == config.path {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        info!(
+            "Spawning {} world objects from {}",
+            objects.objects.len(),
+            config.path
+        );
+        spawn_world_objects(&mut commands, &assets, objects, &config.path);
+    }
+}
+
+fn spawn_world_objects(
+    commands: &mut Commands,
+    assets: &WorldObjectAssets,
+    world_objects: &WorldObjects,
+    layer_name: &str,
+) {
+    commands
+        .spawn((
+            SpatialBundle::default(),
+            Name::new(layer_name.to_string()),
+            WorldObjectsLayer,
+        ))
+        .with_children(|parent| {
+            for object in &world_objects.objects {
+                let [x, z] = object.position;
+                let transform = Transform::from_xyz(x, 0.0, z)
+                    .with_rotation(Quat::from_rotation_y(object.rotation.to_radians()));
+
+                let mut entity = parent.spawn((
+                    Name::new(object.name.clone()),
+                    TerrainAnchor {
+                        height_offset: object.height_offset,
+                    },
+                ));
+
+                match &object.kind {
+                    WorldObjectKind::Obstacle { size } => {
+                        let (mesh, material) = assets.obstacle.clone();
+                        entity.insert(PbrBundle {
+                            mesh,
+                            material,
+                            transform: transform.with_scale(Vec3::from(*size)),
+                            ..default()
+                        });
+                    }
+                    WorldObjectKind::SpawnPoint => {
+                        entity.insert((SpatialBundle::from_transform(transform), SpawnPoint));
+                    }
+                    WorldObjectKind::Light {
+                        color,
+                        intensity,
+                        range,
+                    } => {
+                        let [r, g, b] = *color;
+                        entity.insert(PointLightBundle {
+                            point_light: PointLight {
+                                color: Color::rgb(r, g, b),
+                                intensity: *intensity,
+                                range: *range,
+                                ..default()
+                            },
+                            transform,
+                            ..default()
+                        });
+                    }
+                    WorldObjectKind::Portal { destination } => {
+                        let (mesh, material) = assets.portal.clone();
+                        entity.insert((
+                            PbrBundle {
+                                mesh,
+                                material,
+                                transform: transform
+                                    * Transform::from_rotation(Quat::from_rotation_x(
+                                        std::f32::consts::FRAC_PI_2,
+                                    )),
+                                ..default()
+                            },
+                            Portal {
+                                destination: Vec2::from(*destination),
+                            },
+                        ));
+                    }
+                }
+            }
+        });
+}
+
+/// Moves every [`TerrainAnchor`] to the terrain height below it, when spawned or when the terrain
+/// changes.
+pub fn place_on_terrain(
+    heightmap: Res<Heightmap>,
+    mut q_anchors: Query<(Ref<TerrainAnchor>, &mut Transform)>,
+) {
+    for (anchor, mut transform) in &mut q_anchors {
+        if !heightmap.is_changed() && !anchor.is_added() {
+            continue;
+        }
+
+        let height = heightmap.sample(transform.translation.x, transform.translation.z);
+        transform.translation.y = height * HEIGHT_SCALE + anchor.height_offset;
+    }
+}
@@ -1,22 +1,169 @@
-use bevy::prelude::*;
+use std::{fs, io, path::Path};
+
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    fly_by_cam::CameraUpdate,
+    game_state::{in_game, GameState, OnExitGame},
+    input_context::{
+        cycle_active_controller, ensure_active_controller, in_context, ActiveController,
+        InputBindings, InputContext,
+    },
+    map::{Heightmap, Portal, SpawnPoint, WaterKind, WaterMap, HEIGHT_SCALE},
+    shutdown::ShutdownRequested,
+    ActiveCamera,
+};
+
+/// Speed multiplier applied while the player is swimming.
+const SWIM_SPEED_FACTOR: f32 = 0.5;
+
+/// Key which makes the next player the [`ActiveController`], when testing many local players.
+const NEXT_PLAYER_KEY: KeyCode = KeyCode::Tab;
+
+/// How high above the terrain the player origin is, so the capsule stands on it.
+const PLAYER_HEIGHT_OFFSET: f32 = 1.0;
+
+/// How close, on the `XZ` plane, the player must be to a [`Portal`] to use it.
+const PORTAL_RADIUS: f32 = 1.0;
+
+/// Speed, in units per second, the player walks to its [`MoveTarget`].
+const MOVE_TARGET_SPEED: f32 = 40.0;
+
+/// File the active player is saved to when leaving the game, and restored from when it starts.
+pub const CHARACTER_FILE: &str = "character.ron";
+
+/// Distance, on the `X` axis, between local test players when they are spawned.
+const TEST_PLAYER_SPACING: f32 = 4.0;
+
+/// Adds the player logic which doesn't need input or a renderer, so it can run headless: players
+/// with a [`MoveTarget`] walk to it and players walking into a [`Portal`] are moved to its
+/// destination.
+pub struct PlayerLogicPlugin;
+
+impl Plugin for PlayerLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (move_to_target, use_portals).chain().in_set(PlayerUpdate),
+        );
+    }
+}
 
-use crate::fly_by_cam::FlyByCameraConfig;
+/// [`SystemSet`] where players are moved by [`PlayerLogicPlugin`].
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct PlayerUpdate;
 
+/// Adds [`PlayerLogicPlugin`], spawns the player on a [`SpawnPoint`] when the game starts, moves
+/// the one tagged with [`ActiveController`] while [`GameState::InGame`] and despawns players on
+/// [`OnExitGame`]. The [`ActiveCamera`] follows the active player on [`InputContext::Gameplay`].
+///
+/// The active player is saved to [`CHARACTER_FILE`] on [`OnExitGame`] and [`ShutdownRequested`],
+/// and spawned where it was saved when the position is on the map. Only the active one is saved,
+/// so other test players, spawned by [`PlayerControllerConfig::test_players`], start next to it.
+///
+/// Requires [`GameStatePlugin`](crate::game_state::GameStatePlugin).
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(InputManagerPlugin::<Action>::default())
-            .add_systems(Update, (move_player, toggle_camera))
-            .add_systems(Startup, spawn_player)
-            .init_resource::<PlayerControllerConfig>();
+        app.add_plugins(PlayerLogicPlugin)
+            .init_resource::<PlayerControllerConfig>()
+            .add_plugins(InputManagerPlugin::<Action>::default())
+            .add_event::<ShutdownRequested>()
+            .configure_sets(Update, PlayerUpdate.run_if(in_state(GameState::InGame)))
+            .add_systems(
+                Update,
+                (
+                    (update_input_map, register_bindings)
+                        .run_if(resource_changed::<PlayerControllerConfig>()),
+                    (
+                        cycle_active_controller::<Player>.run_if(
+                            in_context(InputContext::Gameplay)
+                                .and_then(input_just_pressed(NEXT_PLAYER_KEY)),
+                        ),
+                        ensure_active_controller::<Player>,
+                        move_player.run_if(in_context(InputContext::Gameplay)),
+                    )
+                        .chain()
+                        .run_if(in_state(GameState::InGame)),
+                )
+                    .chain()
+                    .before(PlayerUpdate),
+            )
+            .add_systems(
+                Update,
+                // Before the camera is moved by anything else, like a camera path being played
+                follow_player
+                    .after(PlayerUpdate)
+                    .before(CameraUpdate)
+                    .run_if(
+                        in_state(GameState::InGame).and_then(in_context(InputContext::Gameplay)),
+                    ),
+            )
+            .add_systems(
+                Update,
+                save_character.run_if(in_game.and_then(on_event::<ShutdownRequested>())),
+            )
+            .add_systems(Startup, register_bindings)
+            .add_systems(
+                OnTransition {
+                    from: GameState::Loading,
+                    to: GameState::InGame,
+                },
+                spawn_player,
+            )
+            .add_systems(OnExitGame, (save_character, despawn_players).chain());
     }
 }
 
-#[derive(Resource, Default)]
-struct PlayerControllerConfig {
-    active: bool,
+/// Inputs bound to player actions.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayerBindings {
+    /// Move bindings, defaults to `WASD` and the left stick.
+    pub movement: Vec<UserInput>,
+}
+
+impl Default for PlayerBindings {
+    fn default() -> Self {
+        Self {
+            movement: vec![VirtualDPad::wasd().into(), DualAxis::left_stick().into()],
+        }
+    }
+}
+
+impl PlayerBindings {
+    fn input_map(&self) -> InputMap<Action> {
+        let mut input_map = InputMap::default();
+        input_map.insert_multiple(
+            self.movement
+                .iter()
+                .map(|input| (input.clone(), Action::Move)),
+        );
+        input_map
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct PlayerControllerConfig {
+    pub bindings: PlayerBindings,
+    /// Position of the camera relative to the player it follows.
+    pub camera_offset: Vec3,
+    /// Number of local players spawned, to test switching between them. At least one is spawned.
+    pub test_players: u8,
+}
+
+impl Default for PlayerControllerConfig {
+    fn default() -> Self {
+        Self {
+            bindings: default(),
+            camera_offset: Vec3::new(0.0, 6.0, 12.0),
+            test_players: 1,
+        }
+    }
 }
 
 #[derive(Actionlike, PartialEq, PartialOrd, Clone, Copy, Hash, Debug, Reflect)]
@@ -24,60 +171,375 @@ enum Action {
     Move,
 }
 
-#[derive(Component)]
-struct Player;
+#[derive(Debug, Error)]
+pub enum CharacterError {
+    #[error("Failed to access character file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse character file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("Failed to serialize character: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+/// Player state kept between games.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Character {
+    /// Position on the `XZ` plane.
+    pub position: Vec2,
+}
+
+impl Character {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CharacterError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CharacterError> {
+        let content = ron::ser::to_string_pretty(self, default())?;
+        Ok(fs::write(path, content)?)
+    }
+}
+
+/// Tags player entities, among which the one with [`ActiveController`] is controlled.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Player;
+
+/// Point on the `XZ` plane the player walks to, like one clicked on the minimap. It's cleared once
+/// reached, when the player is moved by input or when deep water is on the way.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct MoveTarget(pub Option<Vec2>);
+
+/// Whether the player is inside a [`Portal`] trigger. Portals only fire when entered, so players
+/// moved next to the destination portal don't go right back.
+#[derive(Component, Debug, Default, Clone, Copy)]
+struct InsidePortal(bool);
+
+/// Position on the terrain at `x`, `z`, where the player stands.
+fn ground_position(heightmap: &Heightmap, x: f32, z: f32) -> Vec3 {
+    Vec3::new(
+        x,
+        heightmap.sample(x, z) * HEIGHT_SCALE + PLAYER_HEIGHT_OFFSET,
+        z,
+    )
+}
+
+/// Position after walking `offset` on the `XZ` plane from `translation`, slower while swimming,
+/// or `None` when it would walk into deep water from dry land.
+fn walk(
+    heightmap: &Heightmap,
+    water_map: &WaterMap,
+    translation: Vec3,
+    offset: Vec2,
+) -> Option<Vec3> {
+    let swimming = water_map
+        .at(translation)
+        .is_some_and(WaterKind::is_swimmable);
+    let speed = if swimming { SWIM_SPEED_FACTOR } else { 1.0 };
+
+    let target = translation.xz() + offset * speed;
+
+    // Deep water can only be entered swimming, so don't walk into it from dry land.
+    let walkable = water_map
+        .at(Vec3::new(target.x, 0.0, target.y))
+        .is_none_or(WaterKind::is_walkable);
+
+    (swimming || walkable).then(|| ground_position(heightmap, target.x, target.y))
+}
+
+/// Position on the `XZ` plane the player spawns on: where it was saved, when it's on the map, or
+/// on a [`SpawnPoint`].
+fn spawn_position(
+    heightmap: &Heightmap,
+    q_spawn_points: &Query<&Transform, With<SpawnPoint>>,
+) -> Vec2 {
+    let saved = match Character::load(CHARACTER_FILE) {
+        Ok(character) => Some(character.position),
+        Err(CharacterError::Io(err)) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            warn!("Failed to load character: {}", err);
+            None
+        }
+    };
+
+    // The map may have changed since the character was saved
+    let size = Vec2::new(heightmap.width as f32, heightmap.depth as f32) - 1.0;
+    let saved =
+        saved.filter(|position| position.cmpge(Vec2::ZERO).all() && position.cmple(size).all());
+
+    // Spawn points are children of their world objects layer, which is at the origin. Maps without
+    // one spawn the player on their center
+    match (saved, q_spawn_points.iter().next()) {
+        (Some(position), _) => position,
+        (None, Some(spawn_point)) => spawn_point.translation.xz(),
+        (None, None) => {
+            warn!("Map has no spawn point, spawning the player on its center");
+            Vec2::new(heightmap.width as f32, heightmap.depth as f32) / 2.0
+        }
+    }
+}
+
+fn spawn_player(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    config: Res<PlayerControllerConfig>,
+    heightmap: Res<Heightmap>,
+    q_spawn_points: Query<&Transform, With<SpawnPoint>>,
+) {
+    let position = spawn_position(&heightmap, &q_spawn_points);
+    let mesh = meshes.add(shape::Capsule::default().into());
+
+    // The first one is the active player, since it's spawned first
+    for i in 0..config.test_players.max(1) {
+        let position = position + Vec2::X * TEST_PLAYER_SPACING * i as f32;
+
+        commands.spawn((
+            PbrBundle {
+                mesh: mesh.clone(),
+                transform: Transform::from_translation(ground_position(
+                    &heightmap, position.x, position.y,
+                )),
+                ..default()
+            },
+            InputManagerBundle::<Action> {
+                input_map: config.bindings.input_map(),
+                ..default()
+            },
+            Player,
+            MoveTarget::default(),
+            InsidePortal::default(),
+        ));
+    }
+}
+
+/// Spawns a player without mesh or input, used to run the game logic headless, once the map is
+/// generated and there is no player yet.
+pub fn spawn_logic_player(
+    mut commands: Commands,
+    heightmap: Res<Heightmap>,
+    q_spawn_points: Query<&Transform, With<SpawnPoint>>,
+    q_players: Query<(), With<Player>>,
+) {
+    if !q_players.is_empty() {
+        return;
+    }
 
-fn spawn_player(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let position = spawn_position(&heightmap, &q_spawn_points);
     commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(shape::Capsule::default().into()),
-            ..default()
-        },
-        InputManagerBundle::<Action> {
-            input_map: InputMap::new([(
-                VirtualDPad {
-                    up: KeyCode::W.into(),
-                    down: KeyCode::S.into(),
-                    left: KeyCode::A.into(),
-                    right: KeyCode::D.into(),
-                },
-                Action::Move,
-            )]),
-            ..default()
-        },
+        TransformBundle::from_transform(Transform::from_translation(ground_position(
+            &heightmap, position.x, position.y,
+        ))),
         Player,
+        MoveTarget::default(),
+        InsidePortal::default(),
     ));
 }
 
-fn toggle_camera(
-    input: Res<Input<KeyCode>>,
-    mut player_controller_config: ResMut<PlayerControllerConfig>,
-    mut camera_config: ResMut<FlyByCameraConfig>,
+/// Saves the active player to [`CHARACTER_FILE`], so the next game starts where it left. Other
+/// test players aren't saved.
+fn save_character(q_player: Query<&Transform, (With<Player>, With<ActiveController>)>) {
+    let Ok(transform) = q_player.get_single() else {
+        return;
+    };
+
+    let character = Character {
+        position: transform.translation.xz(),
+    };
+    match character.save(CHARACTER_FILE) {
+        Ok(()) => info!("Character saved to {}", CHARACTER_FILE),
+        Err(err) => error!("Failed to save character: {}", err),
+    }
+}
+
+fn despawn_players(mut commands: Commands, q_players: Query<Entity, With<Player>>) {
+    for entity in &q_players {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn register_bindings(config: Res<PlayerControllerConfig>, mut bindings: ResMut<InputBindings>) {
+    bindings.set(
+        Some(InputContext::Gameplay),
+        "Move",
+        config.bindings.movement.iter().cloned(),
+    );
+    bindings.set(
+        Some(InputContext::Gameplay),
+        "Next player",
+        [NEXT_PLAYER_KEY],
+    );
+}
+
+fn update_input_map(
+    config: Res<PlayerControllerConfig>,
+    mut query: Query<&mut InputMap<Action>, With<Player>>,
 ) {
-    if input.just_released(KeyCode::F1) {
-        player_controller_config.active = !player_controller_config.active;
-        camera_config.active = !camera_config.active;
+    for mut input_map in &mut query {
+        *input_map = config.bindings.input_map();
     }
 }
 
 fn move_player(
-    mut query: Query<(&ActionState<Action>, &mut Transform), With<Player>>,
-    player_controller_config: Res<PlayerControllerConfig>,
+    mut query: Query<
+        (&ActionState<Action>, &mut Transform, &mut MoveTarget),
+        With<ActiveController>,
+    >,
+    heightmap: Res<Heightmap>,
+    water_map: Res<WaterMap>,
     time: Res<Time>,
 ) {
-    if !player_controller_config.active {
-        return;
-    }
+    for (state, mut transform, mut move_target) in &mut query {
+        if !state.pressed(Action::Move) {
+            continue;
+        }
 
-    let (state, mut transform) = query.single_mut();
+        // Input takes over walking to the target
+        move_target.0 = None;
 
-    if state.pressed(Action::Move) {
         let axis_data = state.axis_pair(Action::Move).unwrap();
         let move_value: Vec2 = axis_data.into();
         let forward = transform.forward();
         let right = transform.right();
 
-        transform.translation += move_value.x * forward * time.delta_seconds();
-        transform.translation += move_value.y * right * time.delta_seconds();
+        let offset = (move_value.x * forward + move_value.y * right).xz() * time.delta_seconds();
+
+        if let Some(position) = walk(&heightmap, &water_map, transform.translation, offset) {
+            transform.translation = position;
+        }
+    }
+}
+
+/// Walks players to their [`MoveTarget`], with the same rules as walking by input.
+fn move_to_target(
+    mut query: Query<(&mut Transform, &mut MoveTarget), With<Player>>,
+    heightmap: Res<Heightmap>,
+    water_map: Res<WaterMap>,
+    time: Res<Time>,
+) {
+    for (mut transform, mut move_target) in &mut query {
+        let Some(target) = move_target.0 else {
+            continue;
+        };
+
+        let offset = target - transform.translation.xz();
+        let step = MOVE_TARGET_SPEED * time.delta_seconds();
+        let offset = if offset.length() <= step {
+            move_target.0 = None;
+            offset
+        } else {
+            offset.normalize() * step
+        };
+
+        match walk(&heightmap, &water_map, transform.translation, offset) {
+            Some(position) => transform.translation = position,
+            None => move_target.0 = None,
+        }
+    }
+}
+
+/// Moves players which walk into a [`Portal`] to its destination. Players must leave the trigger
+/// they land on before another portal moves them.
+fn use_portals(
+    heightmap: Res<Heightmap>,
+    q_portals: Query<(&GlobalTransform, &Portal)>,
+    mut q_players: Query<(&mut Transform, &mut InsidePortal), With<Player>>,
+) {
+    for (mut transform, mut inside) in &mut q_players {
+        let position = transform.translation.xz();
+        let portal = q_portals.iter().find(|(portal_transform, _)| {
+            portal_transform.translation().xz().distance(position) <= PORTAL_RADIUS
+        });
+
+        match portal {
+            None => inside.0 = false,
+            Some(_) if inside.0 => (),
+            Some((_, portal)) => {
+                let destination = portal.destination;
+                transform.translation = ground_position(&heightmap, destination.x, destination.y);
+                inside.0 = true;
+            }
+        }
+    }
+}
+
+/// Keeps the [`ActiveCamera`] at [`PlayerControllerConfig::camera_offset`] from the active player,
+/// looking at it.
+fn follow_player(
+    config: Res<PlayerControllerConfig>,
+    q_player: Query<&Transform, (With<Player>, With<ActiveController>)>,
+    mut q_camera: Query<&mut Transform, (ActiveCamera, Without<Player>)>,
+) {
+    let (Ok(player), Ok(mut camera)) = (q_player.get_single(), q_camera.get_single_mut()) else {
+        return;
+    };
+
+    let target = player.translation;
+    *camera =
+        Transform::from_translation(target + config.camera_offset).looking_at(target, Vec3::Y);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn active_players(world: &mut World) -> Vec<Entity> {
+        world
+            .query_filtered::<Entity, (With<Player>, With<ActiveController>)>()
+            .iter(world)
+            .collect()
+    }
+
+    #[test]
+    fn cycles_active_controller_across_players() {
+        let mut world = World::new();
+        let first = world.spawn(Player).id();
+        let second = world.spawn(Player).id();
+
+        world.run_system_once(ensure_active_controller::<Player>);
+        assert_eq!(active_players(&mut world), [first]);
+
+        world.run_system_once(cycle_active_controller::<Player>);
+        assert_eq!(active_players(&mut world), [second]);
+
+        world.run_system_once(cycle_active_controller::<Player>);
+        assert_eq!(active_players(&mut world), [first]);
+    }
+
+    #[test]
+    fn portals_fire_again_only_after_leaving_them() {
+        let mut world = World::new();
+        world.insert_resource(Heightmap::new("", 32, 32));
+
+        // Two-way portals, each landing inside the other one
+        for (position, destination) in [
+            (Vec2::splat(4.0), Vec2::splat(20.0)),
+            (Vec2::splat(20.0), Vec2::splat(4.0)),
+        ] {
+            world.spawn((
+                GlobalTransform::from_translation(Vec3::new(position.x, 0.0, position.y)),
+                Portal { destination },
+            ));
+        }
+        let player = world
+            .spawn((
+                Transform::from_xyz(4.0, 0.0, 4.0),
+                Player,
+                InsidePortal::default(),
+            ))
+            .id();
+        let position = |world: &World| world.get::<Transform>(player).unwrap().translation.xz();
+
+        world.run_system_once(use_portals);
+        assert_eq!(position(&world), Vec2::splat(20.0));
+
+        world.run_system_once(use_portals);
+        assert_eq!(position(&world), Vec2::splat(20.0));
+
+        // Leaving the trigger and walking back in fires it
+        world.get_mut::<Transform>(player).unwrap().translation = Vec3::new(24.0, 0.0, 24.0);
+        world.run_system_once(use_portals);
+        world.get_mut::<Transform>(player).unwrap().translation = Vec3::new(20.0, 0.0, 20.0);
+        world.run_system_once(use_portals);
+        assert_eq!(position(&world), Vec2::splat(4.0));
     }
 }
@@ -0,0 +1,257 @@
+use bevy::{app::AppExit, prelude::*, window::WindowCloseRequested};
+use bevy_inspector_egui::{bevy_egui::EguiContexts, egui};
+
+use crate::input_context::{InputContext, InputContextEvent, InputContextStack};
+
+/// Seconds [`ShutdownRequested`] handlers have to finish before the app exits anyway.
+const SHUTDOWN_TIMEOUT: f32 = 5.0;
+
+/// Asks for confirmation before exiting, on [`ExitRequested`] or when the window is closed, then
+/// sends [`ShutdownRequested`] and waits for its handlers before sending [`AppExit`].
+///
+/// Windows should be added with `close_when_requested` disabled, so closing them is confirmed.
+/// Requires [`InputContextPlugin`](crate::input_context::InputContextPlugin).
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Shutdown>()
+            .init_resource::<ExitDialog>()
+            .add_event::<ExitRequested>()
+            .add_event::<ShutdownRequested>()
+            .add_systems(
+                Update,
+                (open_exit_dialog, close_exit_dialog, exit_dialog).chain(),
+            )
+            .add_systems(Last, wait_shutdown_handlers);
+    }
+}
+
+/// Asks the user to confirm the exit.
+#[derive(Event, Debug, Clone, Copy, Default)]
+pub struct ExitRequested;
+
+/// Sent once the exit is confirmed, so handlers can flush their saves. Handlers which take more
+/// than a frame keep the app open with [`Shutdown::delay`].
+#[derive(Event, Debug, Clone, Copy, Default)]
+pub struct ShutdownRequested;
+
+/// Tracks the shutdown, which exits once every delay is finished or after [`SHUTDOWN_TIMEOUT`].
+#[derive(Resource, Debug, Default)]
+pub struct Shutdown {
+    /// Seconds since the shutdown was requested, or `None` while running.
+    elapsed: Option<f32>,
+    /// Names of the handlers still running.
+    pending: Vec<String>,
+}
+
+impl Shutdown {
+    pub fn is_started(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    /// Keeps the app open until [`Shutdown::finish`] is called with the same `name`.
+    pub fn delay(&mut self, name: impl ToString) {
+        self.pending.push(name.to_string());
+    }
+
+    pub fn finish(&mut self, name: &str) {
+        self.pending.retain(|pending| pending != name);
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct ExitDialog {
+    open: bool,
+    /// Whether [`InputContext::Dialog`] was already pushed, so the dialog is closed once it's
+    /// popped.
+    context_pushed: bool,
+}
+
+pub(crate) fn open_exit_dialog(
+    mut exit_requested: EventReader<ExitRequested>,
+    mut close_requested: EventReader<WindowCloseRequested>,
+    shutdown: Res<Shutdown>,
+    mut dialog: ResMut<ExitDialog>,
+    mut events: EventWriter<InputContextEvent>,
+) {
+    // Both are read, so they don't pile up
+    let requested = exit_requested.read().count() + close_requested.read().count() > 0;
+
+    if requested && !dialog.open && !shutdown.is_started() {
+        dialog.open = true;
+        dialog.context_pushed = false;
+        events.send(InputContextEvent::Push(InputContext::Dialog));
+    }
+}
+
+/// Cancels the dialog once its context is popped, like by `Esc`.
+pub(crate) fn close_exit_dialog(mut dialog: ResMut<ExitDialog>, stack: Res<InputContextStack>) {
+    if !dialog.open {
+        return;
+    }
+
+    if stack.contains(InputContext::Dialog) {
+        dialog.context_pushed = true;
+    } else if dialog.context_pushed {
+        dialog.open = false;
+    }
+}
+
+fn exit_dialog(
+    mut egui_contexts: EguiContexts,
+    mut dialog: ResMut<ExitDialog>,
+    shutdown: Res<Shutdown>,
+    mut events: EventWriter<InputContextEvent>,
+    mut shutdown_requested: EventWriter<ShutdownRequested>,
+) {
+    if shutdown.is_started() {
+        egui::Window::new("Exiting")
+            .collapsible(false)
+            .resizable(false)
+            .show(egui_contexts.ctx_mut(), |ui| {
+                ui.label("Saving...");
+            });
+        return;
+    }
+
+    if !dialog.open {
+        return;
+    }
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    egui::Window::new("Exit game?")
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                confirmed = ui.button("Exit").clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    if confirmed || cancelled {
+        dialog.open = false;
+        events.send(InputContextEvent::Pop(InputContext::Dialog));
+    }
+
+    if confirmed {
+        shutdown_requested.send(ShutdownRequested);
+    }
+}
+
+/// Starts the shutdown on [`ShutdownRequested`] and exits once handlers are done. Handlers get a
+/// whole frame to call [`Shutdown::delay`] before it's checked. The timeout uses real time, since
+/// virtual time is stopped while the game is paused.
+fn wait_shutdown_handlers(
+    time: Res<Time<Real>>,
+    mut shutdown: ResMut<Shutdown>,
+    mut shutdown_requested: EventReader<ShutdownRequested>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let requested = shutdown_requested.read().count() > 0;
+
+    let Some(elapsed) = shutdown.elapsed else {
+        if requested {
+            info!("Shutting down");
+            shutdown.elapsed = Some(0.0);
+        }
+        return;
+    };
+
+    let elapsed = elapsed + time.delta_seconds();
+    shutdown.elapsed = Some(elapsed);
+
+    if shutdown.pending.is_empty() {
+        exit.send(AppExit);
+    } else if elapsed >= SHUTDOWN_TIMEOUT {
+        warn!(
+            "Shutdown handlers didn't finish in {}s, exiting anyway: {}",
+            SHUTDOWN_TIMEOUT,
+            shutdown.pending.join(", ")
+        );
+        exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::event::Events;
+
+    use super::*;
+
+    /// App with only [`wait_shutdown_handlers`], where each update advances time by `delta`
+    /// seconds.
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Shutdown>()
+            .init_resource::<Time<Real>>()
+            .add_event::<ShutdownRequested>()
+            .add_event::<AppExit>()
+            .add_systems(Update, wait_shutdown_handlers);
+        app
+    }
+
+    fn update(app: &mut App, delta: f32) {
+        app.world
+            .resource_mut::<Time<Real>>()
+            .advance_by(Duration::from_secs_f32(delta));
+        app.update();
+    }
+
+    fn exited(app: &App) -> bool {
+        !app.world.resource::<Events<AppExit>>().is_empty()
+    }
+
+    #[test]
+    fn exits_right_away_without_handlers() {
+        let mut app = app();
+
+        app.world.send_event(ShutdownRequested);
+        update(&mut app, 0.1);
+        assert!(app.world.resource::<Shutdown>().is_started());
+        assert!(!exited(&app));
+
+        update(&mut app, 0.1);
+        assert!(exited(&app));
+    }
+
+    #[test]
+    fn waits_for_handlers_to_finish() {
+        let mut app = app();
+
+        app.world.send_event(ShutdownRequested);
+        app.world.resource_mut::<Shutdown>().delay("save");
+        update(&mut app, 0.1);
+        update(&mut app, 1.0);
+        assert!(!exited(&app));
+
+        app.world.resource_mut::<Shutdown>().finish("save");
+        update(&mut app, 0.1);
+        assert!(exited(&app));
+    }
+
+    #[test]
+    fn exits_after_timeout_with_stuck_handler() {
+        let mut app = app();
+
+        app.world.send_event(ShutdownRequested);
+        app.world.resource_mut::<Shutdown>().delay("stuck");
+        update(&mut app, 0.1);
+
+        // Just before the timeout
+        for _ in 0..4 {
+            update(&mut app, 1.0);
+        }
+        update(&mut app, 0.9);
+        assert!(!exited(&app));
+
+        update(&mut app, 0.2);
+        assert!(exited(&app));
+        assert_eq!(app.world.resource::<Shutdown>().pending, ["stuck"]);
+    }
+}
@@ -0,0 +1,465 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{pbr::FogFalloff, prelude::*, utils::HashMap};
+
+use crate::{
+    map::{Biome, BiomeMap, CHUNK_SIZE},
+    rng::Rng,
+    world_time::WorldTimeUpdate,
+    ActiveCamera, MainCamera,
+};
+
+/// Adds [`Wind`] and regional weather resources, and internal systems which drives fog, lights and
+/// precipitation by the weather of the region the [`ActiveCamera`] is in. Fog is added to every
+/// [`MainCamera`].
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Wind>()
+            .register_type::<Wind>()
+            .init_resource::<RegionalWeather>()
+            .init_resource::<CurrentWeather>()
+            .init_resource::<PrecipitationAssets>()
+            .init_resource::<WeatherRng>()
+            .add_systems(
+                Update,
+                (
+                    setup_regions.run_if(resource_changed::<BiomeMap>()),
+                    change_regional_weather,
+                    transition_current_weather,
+                    (
+                        update_wind,
+                        update_fog,
+                        dim_lights.after(WorldTimeUpdate),
+                        spawn_precipitation,
+                        move_precipitation,
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Size, in cells, of each side of a weather region.
+const REGION_SIZE: u16 = CHUNK_SIZE * 2;
+
+/// How many seconds it takes to fully transition from a weather to the next one.
+const TRANSITION_DURATION: f32 = 10.0;
+
+/// Maximum number of precipitation particles alive at the same time.
+const MAX_PARTICLES: usize = 2000;
+
+/// How far from the camera precipitation particles are spawned.
+const PRECIPITATION_RADIUS: f32 = 30.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum WeatherKind {
+    #[default]
+    Clear,
+    Cloudy,
+    Rain,
+    Storm,
+    Fog,
+    Snow,
+}
+
+/// Effects of a [`WeatherKind`], which can be blended together while transitioning.
+#[derive(Debug, Clone, Copy)]
+struct WeatherEffects {
+    /// How far the camera can see, in world units.
+    visibility: f32,
+    /// Multiplier of the sun, moon and ambient light intensity.
+    light: f32,
+    /// Rain drops spawned per second.
+    rain: f32,
+    /// Snowflakes spawned per second.
+    snow: f32,
+    /// Wind speed, in world units per second.
+    wind: f32,
+}
+
+impl WeatherEffects {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            visibility: lerp(self.visibility, other.visibility),
+            light: lerp(self.light, other.light),
+            rain: lerp(self.rain, other.rain),
+            snow: lerp(self.snow, other.snow),
+            wind: lerp(self.wind, other.wind),
+        }
+    }
+}
+
+impl WeatherKind {
+    fn effects(self) -> WeatherEffects {
+        let (visibility, light, rain, snow, wind) = match self {
+            WeatherKind::Clear => (2000.0, 1.0, 0.0, 0.0, 1.0),
+            WeatherKind::Cloudy => (1000.0, 0.6, 0.0, 0.0, 3.0),
+            WeatherKind::Rain => (400.0, 0.4, 600.0, 0.0, 4.0),
+            WeatherKind::Storm => (200.0, 0.2, 1500.0, 0.0, 12.0),
+            WeatherKind::Fog => (60.0, 0.5, 0.0, 0.0, 0.5),
+            WeatherKind::Snow => (300.0, 0.6, 0.0, 400.0, 2.0),
+        };
+
+        WeatherEffects {
+            visibility,
+            light,
+            rain,
+            snow,
+            wind,
+        }
+    }
+
+    /// How likely each weather is to happen on the given biome.
+    fn chances(biome: Biome) -> &'static [(WeatherKind, u32)] {
+        use WeatherKind::*;
+
+        match biome {
+            Biome::Water | Biome::Beach => {
+                &[(Clear, 4), (Cloudy, 3), (Rain, 2), (Storm, 1), (Fog, 2)]
+            }
+            Biome::Desert => &[(Clear, 8), (Cloudy, 1), (Storm, 1)],
+            Biome::Grassland => &[(Clear, 5), (Cloudy, 3), (Rain, 2), (Storm, 1), (Fog, 1)],
+            Biome::Forest => &[(Clear, 3), (Cloudy, 3), (Rain, 3), (Storm, 1), (Fog, 2)],
+            Biome::Mountain => &[(Clear, 3), (Cloudy, 3), (Fog, 2), (Snow, 2), (Storm, 1)],
+            Biome::Snow => &[(Clear, 2), (Cloudy, 3), (Snow, 5), (Fog, 1), (Storm, 1)],
+        }
+    }
+
+    fn random(biome: Biome, rng: &mut Rng) -> Self {
+        let chances = Self::chances(biome);
+        let total = chances.iter().map(|(_, chance)| chance).sum::<u32>();
+        let mut roll = (rng.next_u64() % total as u64) as u32;
+
+        for &(kind, chance) in chances {
+            if roll < chance {
+                return kind;
+            }
+            roll -= chance;
+        }
+
+        WeatherKind::Clear
+    }
+}
+
+/// Wind blowing over the whole map. Vegetation, particles and gameplay can read it.
+#[derive(Resource, Reflect, Debug, Default)]
+#[reflect(Resource)]
+pub struct Wind {
+    /// Wind velocity on the `XZ` plane, in world units per second.
+    pub velocity: Vec2,
+}
+
+impl Wind {
+    pub fn velocity3d(&self) -> Vec3 {
+        Vec3::new(self.velocity.x, 0.0, self.velocity.y)
+    }
+}
+
+#[derive(Resource)]
+struct WeatherRng(Rng);
+
+impl Default for WeatherRng {
+    /// Seeded from the clock, so the weather is different on every session.
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        Self(Rng::new(seed))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    biome: Biome,
+    weather: WeatherKind,
+    /// Seconds until the weather changes.
+    remaining: f32,
+}
+
+/// Weather of each region of the map, chosen from the most common biome on it.
+#[derive(Resource, Debug, Default)]
+struct RegionalWeather {
+    regions_z: u16,
+    regions: Vec<Region>,
+}
+
+impl RegionalWeather {
+    /// Region which contains the given world `position`, clamped to the map.
+    fn at(&self, position: Vec3) -> Option<&Region> {
+        if self.regions.is_empty() {
+            return None;
+        }
+
+        let regions_x = self.regions.len() / self.regions_z as usize;
+        let x = (position.x.max(0.0) as usize / REGION_SIZE as usize).min(regions_x - 1);
+        let z =
+            (position.z.max(0.0) as usize / REGION_SIZE as usize).min(self.regions_z as usize - 1);
+
+        self.regions.get(x * self.regions_z as usize + z)
+    }
+}
+
+/// Weather the [`ActiveCamera`] is experiencing, blending from the previous one.
+#[derive(Resource, Debug, Default)]
+struct CurrentWeather {
+    from: WeatherKind,
+    to: WeatherKind,
+    blend: f32,
+}
+
+impl CurrentWeather {
+    fn effects(&self) -> WeatherEffects {
+        self.from
+            .effects()
+            .lerp(self.to.effects(), self.blend.clamp(0.0, 1.0))
+    }
+}
+
+fn random_duration(rng: &mut Rng) -> f32 {
+    // Between 2 and 5 minutes
+    120.0 + rng.next_f32() * 180.0
+}
+
+fn setup_regions(
+    biome_map: Res<BiomeMap>,
+    mut regional_weather: ResMut<RegionalWeather>,
+    mut rng: ResMut<WeatherRng>,
+) {
+    let regions_x = biome_map.width().div_ceil(REGION_SIZE);
+    let regions_z = biome_map.depth().div_ceil(REGION_SIZE);
+
+    let mut regions = vec![];
+    for region_x in 0..regions_x {
+        for region_z in 0..regions_z {
+            let mut count = HashMap::<Biome, u32>::new();
+            for x in region_x * REGION_SIZE..((region_x + 1) * REGION_SIZE).min(biome_map.width()) {
+                for z in
+                    region_z * REGION_SIZE..((region_z + 1) * REGION_SIZE).min(biome_map.depth())
+                {
+                    *count.entry(biome_map.get(x, z)).or_default() += 1;
+                }
+            }
+
+            let biome = count
+                .into_iter()
+                .max_by_key(|&(_, count)| count)
+                .map(|(biome, _)| biome)
+                .unwrap_or_default();
+
+            regions.push(Region {
+                biome,
+                weather: WeatherKind::random(biome, &mut rng.0),
+                remaining: random_duration(&mut rng.0),
+            });
+        }
+    }
+
+    *regional_weather = RegionalWeather { regions_z, regions };
+}
+
+fn change_regional_weather(
+    time: Res<Time>,
+    mut regional_weather: ResMut<RegionalWeather>,
+    mut rng: ResMut<WeatherRng>,
+) {
+    for region in regional_weather.regions.iter_mut() {
+        region.remaining -= time.delta_seconds();
+
+        if region.remaining <= 0.0 {
+            region.weather = WeatherKind::random(region.biome, &mut rng.0);
+            region.remaining = random_duration(&mut rng.0);
+        }
+    }
+}
+
+fn transition_current_weather(
+    time: Res<Time>,
+    regional_weather: Res<RegionalWeather>,
+    mut current_weather: ResMut<CurrentWeather>,
+    q_camera: Query<&GlobalTransform, ActiveCamera>,
+) {
+    let Ok(transform) = q_camera.get_single() else {
+        return;
+    };
+
+    let weather = regional_weather
+        .at(transform.translation())
+        .map(|region| region.weather)
+        .unwrap_or_default();
+
+    if weather != current_weather.to {
+        // Starts the new transition from whatever is the closest to the current weather
+        let from = if current_weather.blend < 0.5 {
+            current_weather.from
+        } else {
+            current_weather.to
+        };
+
+        *current_weather = CurrentWeather {
+            from,
+            to: weather,
+            blend: 0.0,
+        };
+    }
+
+    current_weather.blend += time.delta_seconds() / TRANSITION_DURATION;
+}
+
+fn update_wind(time: Res<Time>, current_weather: Res<CurrentWeather>, mut wind: ResMut<Wind>) {
+    // Slowly rotates the wind direction over time
+    let direction = Vec2::from_angle(time.elapsed_seconds() * 0.01);
+    wind.velocity = direction * current_weather.effects().wind;
+}
+
+fn update_fog(
+    mut commands: Commands,
+    current_weather: Res<CurrentWeather>,
+    mut q_camera: Query<(Entity, Option<&mut FogSettings>), With<MainCamera>>,
+) {
+    let falloff = FogFalloff::from_visibility(current_weather.effects().visibility);
+
+    for (entity, fog) in &mut q_camera {
+        if let Some(mut fog) = fog {
+            fog.falloff = falloff.clone();
+        } else {
+            commands.entity(entity).insert(FogSettings {
+                color: Color::rgb(0.6, 0.65, 0.7),
+                falloff: falloff.clone(),
+                ..default()
+            });
+        }
+    }
+}
+
+/// Dims lights set by [`WorldTimeUpdate`] systems, so it must run after them. Every directional
+/// light is either the sun or the moon.
+fn dim_lights(
+    current_weather: Res<CurrentWeather>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut q_lights: Query<&mut DirectionalLight>,
+) {
+    let light = current_weather.effects().light;
+
+    ambient_light.brightness *= light;
+    for mut directional_light in &mut q_lights {
+        directional_light.illuminance *= light;
+    }
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec3,
+}
+
+#[derive(Resource)]
+struct PrecipitationAssets {
+    rain: (Handle<Mesh>, Handle<StandardMaterial>),
+    snow: (Handle<Mesh>, Handle<StandardMaterial>),
+}
+
+impl FromWorld for PrecipitationAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let rain_mesh = meshes.add(shape::Box::new(0.02, 0.4, 0.02).into());
+        let snow_mesh = meshes.add(shape::Cube::new(0.08).into());
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        Self {
+            rain: (
+                rain_mesh,
+                materials.add(StandardMaterial {
+                    base_color: Color::rgba(0.7, 0.75, 0.85, 0.5),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                }),
+            ),
+            snow: (
+                snow_mesh,
+                materials.add(StandardMaterial {
+                    base_color: Color::WHITE,
+                    unlit: true,
+                    ..default()
+                }),
+            ),
+        }
+    }
+}
+
+fn spawn_precipitation(
+    mut commands: Commands,
+    time: Res<Time>,
+    current_weather: Res<CurrentWeather>,
+    assets: Res<PrecipitationAssets>,
+    mut rng: ResMut<WeatherRng>,
+    q_particles: Query<(), With<Particle>>,
+    q_camera: Query<&GlobalTransform, ActiveCamera>,
+) {
+    let Ok(camera) = q_camera.get_single() else {
+        return;
+    };
+
+    let effects = current_weather.effects();
+    let rate = effects.rain + effects.snow;
+    if rate <= 0.0 {
+        return;
+    }
+
+    let rng = &mut rng.0;
+    // Randomly rounded, so low rates still spawns some particles over time
+    let count = (rate * time.delta_seconds() + rng.next_f32()) as usize;
+    let count = count.min(MAX_PARTICLES.saturating_sub(q_particles.iter().len()));
+
+    for _ in 0..count {
+        let is_rain = rng.next_f32() * rate < effects.rain;
+        let ((mesh, material), fall_speed) = if is_rain {
+            (assets.rain.clone(), 15.0)
+        } else {
+            (assets.snow.clone(), 1.5)
+        };
+
+        let offset = Vec3::new(
+            (rng.next_f32() * 2.0 - 1.0) * PRECIPITATION_RADIUS,
+            PRECIPITATION_RADIUS / 2.0,
+            (rng.next_f32() * 2.0 - 1.0) * PRECIPITATION_RADIUS,
+        );
+
+        commands.spawn((
+            PbrBundle {
+                mesh,
+                material,
+                transform: Transform::from_translation(camera.translation() + offset),
+                ..default()
+            },
+            Particle {
+                velocity: Vec3::NEG_Y * fall_speed,
+            },
+        ));
+    }
+}
+
+fn move_precipitation(
+    mut commands: Commands,
+    time: Res<Time>,
+    wind: Res<Wind>,
+    mut q_particles: Query<(Entity, &Particle, &mut Transform)>,
+    q_camera: Query<&GlobalTransform, ActiveCamera>,
+) {
+    let Ok(camera) = q_camera.get_single() else {
+        return;
+    };
+
+    let floor = camera.translation().y - PRECIPITATION_RADIUS / 2.0;
+
+    for (entity, particle, mut transform) in &mut q_particles {
+        transform.translation += (particle.velocity + wind.velocity3d()) * time.delta_seconds();
+
+        if transform.translation.y < floor {
+            commands.entity(entity).despawn();
+        }
+    }
+}
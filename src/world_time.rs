@@ -0,0 +1,177 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+
+/// Adds [`WorldTime`] resource, which advances every frame, without anything which needs a
+/// renderer, so it can run headless.
+pub struct WorldTimeLogicPlugin;
+
+impl Plugin for WorldTimeLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldTime>()
+            .register_type::<WorldTime>()
+            .add_systems(Update, advance_world_time.in_set(WorldTimeUpdate));
+    }
+}
+
+/// Adds [`WorldTimeLogicPlugin`] and the sun and moon lights driven by [`WorldTime`].
+pub struct WorldTimePlugin;
+
+impl Plugin for WorldTimePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(WorldTimeLogicPlugin)
+            .add_systems(Startup, spawn_sun)
+            .add_systems(
+                Update,
+                (update_sun, update_ambient_light, toggle_moon)
+                    .in_set(WorldTimeUpdate)
+                    .after(advance_world_time),
+            );
+    }
+}
+
+/// [`SystemSet`] used by internal systems. Systems which depends on the current [`WorldTime`]
+/// should run after it.
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct WorldTimeUpdate;
+
+/// Illuminance of the sun when it's at the zenith.
+const SUN_ILLUMINANCE: f32 = 100000.0;
+
+/// Illuminance of the moon when it's at the zenith.
+const MOON_ILLUMINANCE: f32 = 5000.0;
+
+/// In-game time, shared by all systems which depends on the time of the day.
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct WorldTime {
+    /// How many real seconds an in-game day lasts.
+    pub day_length: f32,
+    /// How many in-game days have passed.
+    pub day: u32,
+    /// Current hour of the day, in range [0, 24).
+    pub hour: f32,
+}
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self {
+            day_length: 20.0 * 60.0,
+            day: 0,
+            hour: 9.0,
+        }
+    }
+}
+
+impl WorldTime {
+    /// Angle of the sun above the horizon, in radians. It rises at 6h and sets at 18h.
+    pub fn sun_angle(&self) -> f32 {
+        (self.hour - 6.0) / 24.0 * TAU
+    }
+
+    /// Sun is below the horizon.
+    pub fn is_night(&self) -> bool {
+        self.sun_angle().sin() < 0.0
+    }
+}
+
+/// Tags the directional light driven by the time of the day.
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Component)]
+struct Moon;
+
+fn spawn_sun(mut commands: Commands) {
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        Name::new("Sun"),
+        Sun,
+    ));
+}
+
+fn advance_world_time(time: Res<Time>, mut world_time: ResMut<WorldTime>) {
+    if world_time.day_length <= 0.0 {
+        return;
+    }
+
+    world_time.hour += time.delta_seconds() / world_time.day_length * 24.0;
+    while world_time.hour >= 24.0 {
+        world_time.hour -= 24.0;
+        world_time.day += 1;
+    }
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let [r0, g0, b0, _] = from.as_rgba_f32();
+    let [r1, g1, b1, _] = to.as_rgba_f32();
+
+    Color::rgb(r0 + (r1 - r0) * t, g0 + (g1 - g0) * t, b0 + (b1 - b0) * t)
+}
+
+/// Light pointing from a celestial body `angle` radians above the horizon.
+fn celestial_rotation(angle: f32) -> Quat {
+    // Slightly tilted, so the light never comes exactly from the zenith
+    Quat::from_rotation_y(PI / 8.0) * Quat::from_rotation_x(-angle)
+}
+
+fn update_sun(
+    world_time: Res<WorldTime>,
+    mut q_sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
+    let angle = world_time.sun_angle();
+    let elevation = angle.sin().max(0.0);
+
+    for (mut transform, mut light) in &mut q_sun {
+        transform.rotation = celestial_rotation(angle);
+        light.illuminance = SUN_ILLUMINANCE * elevation;
+        // Reddish when close to the horizon, white at noon
+        light.color = lerp_color(Color::rgb(1.0, 0.6, 0.35), Color::WHITE, elevation.sqrt());
+    }
+}
+
+fn update_ambient_light(world_time: Res<WorldTime>, mut ambient_light: ResMut<AmbientLight>) {
+    let daylight = world_time.sun_angle().sin().clamp(0.0, 1.0);
+
+    ambient_light.color = lerp_color(Color::rgb(0.3, 0.35, 0.6), Color::WHITE, daylight);
+    ambient_light.brightness = 0.02 + 0.28 * daylight;
+}
+
+fn toggle_moon(
+    mut commands: Commands,
+    world_time: Res<WorldTime>,
+    mut q_moon: Query<(Entity, &mut Transform, &mut DirectionalLight), With<Moon>>,
+) {
+    // Moon is always on the opposite side of the sun
+    let angle = world_time.sun_angle() + PI;
+
+    match (world_time.is_night(), q_moon.get_single_mut()) {
+        (true, Ok((_, mut transform, mut light))) => {
+            transform.rotation = celestial_rotation(angle);
+            light.illuminance = MOON_ILLUMINANCE * angle.sin().max(0.0);
+        }
+        (true, Err(_)) => {
+            commands.spawn((
+                DirectionalLightBundle {
+                    directional_light: DirectionalLight {
+                        color: Color::rgb(0.6, 0.7, 1.0),
+                        illuminance: MOON_ILLUMINANCE * angle.sin().max(0.0),
+                        ..default()
+                    },
+                    transform: Transform::from_rotation(celestial_rotation(angle)),
+                    ..default()
+                },
+                Name::new("Moon"),
+                Moon,
+            ));
+        }
+        (false, Ok((entity, _, _))) => commands.entity(entity).despawn(),
+        (false, Err(_)) => (),
+    }
+}